@@ -1,4 +1,7 @@
-use std::{borrow::Cow, collections::HashMap};
+use std::{
+  borrow::Cow,
+  collections::{HashMap, HashSet},
+};
 
 use eframe::{
   egui::{self, Checkbox, TextStyle},
@@ -6,7 +9,10 @@ use eframe::{
 };
 use egui_node_graph::*;
 
-use complogic::{And, Compiler, Gate, Simulation};
+use complogic::{
+  gate_info, op_inputs, op_output, And, CompileError, Compiler, FourBitAdder,
+  Gate, Not, Simulation,
+};
 
 // ========= First, define your user data types =============
 
@@ -20,10 +26,13 @@ pub struct NodeData {
 
 /// `DataType`s are what defines the possible range of connections when
 /// attaching two ports together. The graph UI will make sure to not allow
-/// attaching incompatible datatypes.
-#[derive(PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+/// attaching incompatible datatypes. Buses only connect to buses of the same
+/// width.
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum DataType {
   Scalar,
+  Number,
+  Bus { width: usize },
 }
 
 /// In the graph, input parameters can optionally have a constant value. This
@@ -35,7 +44,17 @@ pub enum DataType {
 /// with a DataType of Scalar and a ValueType of Vec2.
 #[derive(Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum ValueType {
-  Scalar { value: bool },
+  Scalar {
+    value: bool,
+  },
+  Number {
+    value: u32,
+  },
+  /// The bits of a bus, with the least significant bit first
+  Bus {
+    width: usize,
+    value: u32,
+  },
 }
 
 impl Default for ValueType {
@@ -49,8 +68,44 @@ impl Default for ValueType {
 impl ValueType {
   /// Tries to downcast this value type to a scalar
   pub fn try_to_scalar(self) -> anyhow::Result<bool> {
-    let ValueType::Scalar { value } = self;
-    Ok(value)
+    if let ValueType::Scalar { value } = self {
+      Ok(value)
+    } else {
+      anyhow::bail!("Invalid cast from {:?} to scalar", self)
+    }
+  }
+
+  /// Tries to downcast this value type to a number
+  pub fn try_to_number(self) -> anyhow::Result<u32> {
+    if let ValueType::Number { value } = self {
+      Ok(value)
+    } else {
+      anyhow::bail!("Invalid cast from {:?} to number", self)
+    }
+  }
+
+  /// Tries to downcast this value type to the value of a bus
+  pub fn try_to_bus(self) -> anyhow::Result<u32> {
+    if let ValueType::Bus { value, .. } = self {
+      Ok(value)
+    } else {
+      anyhow::bail!("Invalid cast from {:?} to bus", self)
+    }
+  }
+
+  /// Tries to split the value of a bus into its bits, with the least
+  /// significant bit first, checking that the value fits in the bus
+  pub fn try_to_bits(self) -> anyhow::Result<Vec<bool>> {
+    let (width, value) = match self {
+      ValueType::Bus { width, value } => (width, value),
+      _ => anyhow::bail!("Invalid cast from {:?} to bits", self),
+    };
+
+    if width < 32 && value >> width != 0 {
+      anyhow::bail!("{} doesn't fit in {} bits", value, width);
+    }
+
+    Ok((0..width).map(|i| i < 32 && value >> i & 1 == 1).collect())
   }
 }
 
@@ -60,7 +115,15 @@ impl ValueType {
 #[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum NodeTempl {
   And,
+  Not,
+  FourBitAdder,
   Immediate,
+  /// An immediate that drives a bus with the bits of a number
+  BusImmediate {
+    width: usize,
+  },
+  Clock,
+  Output,
 }
 
 /// The response type is used to encode side-effects produced when drawing a
@@ -83,9 +146,178 @@ pub struct GraphState {
   pub compiler: Compiler,
   pub gates: HashMap<NodeId, Gate>,
   pub outs_to_regs: HashMap<OutputId, usize>,
+  /// The registers of each bus output, with the least significant bit first
+  pub outs_to_buses: HashMap<OutputId, Vec<usize>>,
+  /// The register of each bit of the outputs before the last recompile,
+  /// which are reused so the registers don't jump around
+  #[serde(skip)]
+  pub reg_hint: HashMap<(OutputId, usize), usize>,
   pub regs_to_outs: HashMap<usize, OutputId>,
   pub immediates: HashMap<OutputId, (usize, bool)>,
+  /// The register and value of each bit of the bus immediates
+  pub bus_immediates: HashMap<OutputId, Vec<(usize, bool)>>,
+  /// The frames counted since the last flip and the value of each clock
+  pub clocks: HashMap<NodeId, (u32, bool)>,
   pub connections: usize,
+  /// The error of the last compile, if it failed
+  #[serde(skip)]
+  pub compile_error: Option<String>,
+  /// The index of the next op to run while stepping through the ops one at
+  /// a time, otherwise the whole simulation runs whenever something changes
+  #[serde(skip)]
+  pub pc: Option<usize>,
+  /// The truth table of the active node and the node it's for, which is
+  /// cleared whenever the circuit is recompiled
+  #[serde(skip)]
+  pub truth_table: Option<(NodeId, TruthTable)>,
+}
+
+/// The columns and rows of the truth table of a node
+pub struct TruthTable {
+  pub inputs: Vec<(String, usize)>,
+  pub outputs: Vec<(String, usize)>,
+  /// The rows of the table, which are only swept when there are few enough
+  /// inputs
+  pub rows: Option<Vec<(Vec<bool>, Vec<bool>)>>,
+}
+
+impl GraphState {
+  /// Gets the register of an output, allocating one if it doesn't have one yet
+  pub fn output_register(&mut self, out_id: OutputId) -> usize {
+    match self.outs_to_regs.get(&out_id) {
+      Some(reg) => *reg,
+      None => {
+        let reg = self.compiler.alloc_hinted(&(out_id, 0), &self.reg_hint);
+        self.outs_to_regs.insert(out_id, reg);
+        reg
+      }
+    }
+  }
+
+  /// Gets the registers of a bus output, allocating them if it doesn't have
+  /// them yet
+  pub fn output_bus(&mut self, out_id: OutputId, width: usize) -> Vec<usize> {
+    if let Some(regs) = self.outs_to_buses.get(&out_id) {
+      return regs.clone();
+    }
+
+    let regs: Vec<usize> = (0..width)
+      .map(|bit| self.compiler.alloc_hinted(&(out_id, bit), &self.reg_hint))
+      .collect();
+    self.outs_to_buses.insert(out_id, regs.clone());
+    regs
+  }
+
+  /// Gets the simulated value of a bus output, zero if it has no registers
+  pub fn bus_value(&self, out_id: OutputId) -> u32 {
+    self
+      .outs_to_buses
+      .get(&out_id)
+      .map(|regs| {
+        regs
+          .iter()
+          .enumerate()
+          .filter(|(_, reg)| self.simulation.register(**reg))
+          .fold(0, |value, (i, _)| value | 1 << i)
+      })
+      .unwrap_or(0)
+  }
+
+  /// Gets the simulated value of each output, low if it has no register
+  pub fn output_values(
+    &self,
+    out_ids: impl Iterator<Item = OutputId>,
+  ) -> Vec<(OutputId, bool)> {
+    out_ids
+      .map(|out_id| {
+        let value = self
+          .outs_to_regs
+          .get(&out_id)
+          .map(|reg| self.simulation.register(*reg))
+          .unwrap_or(false);
+        (out_id, value)
+      })
+      .collect()
+  }
+
+  /// Compiles the gates and runs the simulation with the captured immediates
+  ///
+  /// While stepping, the simulation isn't run and stepping starts over from
+  /// the first op instead.
+  pub fn simulate(&mut self) -> Result<(), CompileError> {
+    self.truth_table = None;
+    self.simulation = self
+      .compiler
+      .compile(self.gates.values().collect::<Vec<_>>())?;
+
+    if self.pc.is_some() {
+      self.pc = Some(0);
+      return Ok(());
+    }
+
+    let immediates = self.immediate_values();
+    self.simulation.run(&immediates);
+
+    Ok(())
+  }
+
+  /// Runs the next op while stepping, starting to step if the simulation was
+  /// running freely
+  pub fn step(&mut self) {
+    let immediates = self.immediate_values();
+    let pc = self.pc.unwrap_or(0);
+    self.pc = Some(self.simulation.step(pc, &immediates));
+  }
+
+  /// Gets the registers the next op reads from and writes to while stepping
+  pub fn stepped_registers(&self) -> Vec<usize> {
+    self
+      .pc
+      .and_then(|pc| self.simulation.ops.get(pc))
+      .map(|op| {
+        let mut regs = op_inputs(*op);
+        regs.push(op_output(*op));
+        regs
+      })
+      .unwrap_or_default()
+  }
+
+  /// Gets the captured value of every immediate
+  pub fn immediate_values(&self) -> Vec<bool> {
+    let mut immediates: Vec<bool> = vec![false; self.compiler.immediate_count];
+
+    self.immediates.iter().for_each(|(_, (index, val))| {
+      immediates[*index] = *val;
+    });
+    self
+      .bus_immediates
+      .values()
+      .flatten()
+      .for_each(|(index, val)| {
+        immediates[*index] = *val;
+      });
+
+    immediates
+  }
+}
+
+/// A node graph as it is saved from the editor, along with the compiler
+/// and the registers of its nodes. This isn't a [`complogic::CircuitFile`],
+/// which only stores the gates. JSON maps need string keys, so the maps of
+/// the graph state are stored as lists of pairs.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct EditorFile<S> {
+  state: S,
+  compiler: Compiler,
+  gates: Vec<(NodeId, Gate)>,
+  outs_to_regs: Vec<(OutputId, usize)>,
+  #[serde(default)]
+  outs_to_buses: Vec<(OutputId, Vec<usize>)>,
+  immediates: Vec<(OutputId, (usize, bool))>,
+  #[serde(default)]
+  bus_immediates: Vec<(OutputId, Vec<(usize, bool)>)>,
+  #[serde(default)]
+  clocks: Vec<(NodeId, (u32, bool))>,
 }
 
 // =========== Then, you need to implement some traits ============
@@ -95,12 +327,16 @@ impl DataTypeTrait<GraphState> for DataType {
   fn data_type_color(&self, _user_state: &mut GraphState) -> ecolor::Color32 {
     match self {
       DataType::Scalar => egui::Color32::from_rgb(38, 109, 211),
+      DataType::Number => egui::Color32::from_rgb(238, 207, 109),
+      DataType::Bus { .. } => egui::Color32::from_rgb(109, 211, 138),
     }
   }
 
   fn name(&self) -> Cow<'_, str> {
     match self {
       DataType::Scalar => Cow::Borrowed("scalar"),
+      DataType::Number => Cow::Borrowed("number"),
+      DataType::Bus { width } => Cow::Owned(format!("bus{}", width)),
     }
   }
 }
@@ -120,7 +356,14 @@ impl NodeTemplateTrait for NodeTempl {
   ) -> Cow<'_, str> {
     Cow::Borrowed(match self {
       NodeTempl::And => "And Gate",
+      NodeTempl::Not => "Not Gate",
+      NodeTempl::FourBitAdder => "4-Bit Adder",
       NodeTempl::Immediate => "Immediate",
+      NodeTempl::BusImmediate { width } => {
+        return Cow::Owned(format!("{}-Bit Immediate", width))
+      }
+      NodeTempl::Clock => "Clock",
+      NodeTempl::Output => "Output",
     })
   }
 
@@ -129,9 +372,20 @@ impl NodeTemplateTrait for NodeTempl {
     &self,
     _user_state: &mut Self::UserState,
   ) -> Vec<&'static str> {
-    match self {
-      NodeTempl::And => vec!["Gate"],
-      NodeTempl::Immediate => vec!["Tools"],
+    // Gates are listed under their category in the gate catalog
+    let gate = match self {
+      NodeTempl::And => gate_info("And"),
+      NodeTempl::Not => gate_info("Not"),
+      NodeTempl::FourBitAdder => gate_info("FourBitAdder"),
+      NodeTempl::Immediate
+      | NodeTempl::BusImmediate { .. }
+      | NodeTempl::Clock
+      | NodeTempl::Output => None,
+    };
+
+    match gate {
+      Some(info) => vec![info.category],
+      None => vec!["Tools"],
     }
   }
 
@@ -171,6 +425,25 @@ impl NodeTemplateTrait for NodeTempl {
       graph.add_output_param(node_id, name.to_string(), DataType::Scalar);
     };
 
+    let input_bus = |graph: &mut MyGraph, name: &str, width: usize| {
+      graph.add_input_param(
+        node_id,
+        name.to_string(),
+        DataType::Bus { width },
+        ValueType::Bus { width, value: 0 },
+        InputParamKind::ConnectionOrConstant,
+        true,
+      );
+    };
+
+    let output_bus = |graph: &mut MyGraph, name: &str, width: usize| {
+      graph.add_output_param(
+        node_id,
+        name.to_string(),
+        DataType::Bus { width },
+      );
+    };
+
     match self {
       NodeTempl::And => {
         // The first input param doesn't use the closure so we can comment
@@ -193,6 +466,16 @@ impl NodeTemplateTrait for NodeTempl {
         input_scalar(graph, "B");
         output_scalar(graph, "out");
       }
+      NodeTempl::Not => {
+        input_scalar(graph, "A");
+        output_scalar(graph, "out");
+      }
+      NodeTempl::FourBitAdder => {
+        input_bus(graph, "A", 4);
+        input_bus(graph, "B", 4);
+        output_bus(graph, "sum", 4);
+        output_scalar(graph, "cout");
+      }
       NodeTempl::Immediate => {
         // The first input param doesn't use the closure so we can comment
         // it in more detail.
@@ -213,6 +496,44 @@ impl NodeTemplateTrait for NodeTempl {
         );
         output_scalar(graph, "out");
       }
+      NodeTempl::BusImmediate { width } => {
+        // The number is entered inline and can't be connected
+        graph.add_input_param(
+          node_id,
+          "value".into(),
+          DataType::Bus { width: *width },
+          ValueType::Bus {
+            width: *width,
+            value: 0,
+          },
+          InputParamKind::ConstantOnly,
+          true,
+        );
+        output_bus(graph, "out", *width);
+      }
+      NodeTempl::Clock => {
+        // The number of frames between each flip of the clock
+        graph.add_input_param(
+          node_id,
+          "frames".into(),
+          DataType::Number,
+          ValueType::Number { value: 30 },
+          InputParamKind::ConstantOnly,
+          true,
+        );
+        output_scalar(graph, "out");
+      }
+      NodeTempl::Output => {
+        // Outputs only display a signal, so they don't take an inline value
+        graph.add_input_param(
+          node_id,
+          "in".into(),
+          DataType::Scalar,
+          ValueType::Scalar { value: false },
+          InputParamKind::ConnectionOnly,
+          true,
+        );
+      }
     }
   }
 }
@@ -225,7 +546,16 @@ impl NodeTemplateIter for AllNodeTempls {
     // This function must return a list of node kinds, which the node finder
     // will use to display it to the user. Crates like strum can reduce the
     // boilerplate in enumerating all variants of an enum.
-    vec![NodeTempl::And, NodeTempl::Immediate]
+    vec![
+      NodeTempl::And,
+      NodeTempl::Not,
+      NodeTempl::FourBitAdder,
+      NodeTempl::Immediate,
+      NodeTempl::BusImmediate { width: 4 },
+      NodeTempl::BusImmediate { width: 8 },
+      NodeTempl::Clock,
+      NodeTempl::Output,
+    ]
   }
 }
 
@@ -250,6 +580,19 @@ impl WidgetValueTrait for ValueType {
           ui.add(Checkbox::new(value, ""));
         });
       }
+      ValueType::Number { value } => {
+        ui.horizontal(|ui| {
+          ui.label(param_name);
+          ui.add(egui::DragValue::new(value).clamp_range(1..=600));
+        });
+      }
+      ValueType::Bus { width, value } => {
+        ui.horizontal(|ui| {
+          ui.label(param_name);
+          let max = u32::MAX >> (32 - (*width).clamp(1, 32));
+          ui.add(egui::DragValue::new(value).clamp_range(0..=max));
+        });
+      }
     }
     // This allows you to return your responses from the inline widgets.
     Vec::new()
@@ -272,7 +615,7 @@ impl NodeDataTrait for NodeData {
     &self,
     ui: &mut egui::Ui,
     node_id: NodeId,
-    _graph: &Graph<NodeData, DataType, ValueType>,
+    graph: &Graph<NodeData, DataType, ValueType>,
     user_state: &mut Self::UserState,
   ) -> Vec<NodeResponse<MyResponse, NodeData>>
   where
@@ -307,8 +650,15 @@ impl NodeDataTrait for NodeData {
       }
     }
 
-    let outputs = &_graph[node_id].outputs;
+    let stepped = user_state.stepped_registers();
+    let outputs = &graph[node_id].outputs;
     for (name, id) in outputs.iter() {
+      // Buses show their value as a number instead of a light
+      if let DataType::Bus { .. } = graph[*id].typ {
+        ui.label(format!("{}: {}", name, user_state.bus_value(*id)));
+        continue;
+      }
+
       let reg = user_state.outs_to_regs.get(id);
       let value = match reg {
         Some(reg) => user_state.simulation.register(*reg),
@@ -327,9 +677,37 @@ impl NodeDataTrait for NodeData {
         true => egui::Color32::GREEN,
         false => egui::Color32::RED,
       });
+
+      // Outline the registers of the op that runs on the next step
+      let button = match reg.filter(|reg| stepped.contains(*reg)) {
+        Some(_) => button.stroke(egui::Stroke::new(3.0, egui::Color32::GOLD)),
+        None => button,
+      };
       ui.add(button);
     }
 
+    // Outputs draw an indicator for the value of whatever is connected to them
+    if let NodeTempl::Output = self.template {
+      let value = graph[node_id]
+        .get_input("in")
+        .ok()
+        .and_then(|in_id| graph.connection(in_id))
+        .and_then(|out_id| user_state.outs_to_regs.get(&out_id))
+        .and_then(|reg| user_state.simulation.registers.get(*reg).copied())
+        .unwrap_or(false);
+
+      let (rect, _) =
+        ui.allocate_exact_size(egui::vec2(48.0, 48.0), egui::Sense::hover());
+      ui.painter().circle_filled(
+        rect.center(),
+        24.0,
+        match value {
+          true => egui::Color32::GREEN,
+          false => egui::Color32::RED,
+        },
+      );
+    }
+
     responses
   }
 }
@@ -345,6 +723,12 @@ pub struct NodeGraphExample {
   state: MyEditorState,
 
   user_state: GraphState,
+
+  /// The path of the circuit file to save to or open
+  path: String,
+
+  /// The error of the last save or open, if it failed
+  file_error: Option<String>,
 }
 
 const PERSISTENCE_KEY: &str = "egui_node_graph";
@@ -359,9 +743,86 @@ impl NodeGraphExample {
       .unwrap_or_default();
     Self {
       state,
-      user_state: GraphState::default(),
+      path: "circuit.json".into(),
+      ..Default::default()
     }
   }
+
+  /// Saves the circuit to the JSON file at `path`
+  fn save_file(&self) -> anyhow::Result<()> {
+    let file = EditorFile {
+      state: &self.state,
+      compiler: self.user_state.compiler.clone(),
+      gates: self
+        .user_state
+        .gates
+        .iter()
+        .map(|(id, gate)| (*id, gate.clone()))
+        .collect(),
+      outs_to_regs: self
+        .user_state
+        .outs_to_regs
+        .iter()
+        .map(|(id, reg)| (*id, *reg))
+        .collect(),
+      outs_to_buses: self
+        .user_state
+        .outs_to_buses
+        .iter()
+        .map(|(id, regs)| (*id, regs.clone()))
+        .collect(),
+      immediates: self
+        .user_state
+        .immediates
+        .iter()
+        .map(|(id, immediate)| (*id, *immediate))
+        .collect(),
+      bus_immediates: self
+        .user_state
+        .bus_immediates
+        .iter()
+        .map(|(id, bits)| (*id, bits.clone()))
+        .collect(),
+      clocks: self
+        .user_state
+        .clocks
+        .iter()
+        .map(|(id, clock)| (*id, *clock))
+        .collect(),
+    };
+
+    std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)?;
+    Ok(())
+  }
+
+  /// Opens the circuit from the JSON file at `path`
+  fn open_file(&mut self) -> anyhow::Result<()> {
+    let file: EditorFile<MyEditorState> =
+      serde_json::from_str(&std::fs::read_to_string(&self.path)?)?;
+
+    self.state = file.state;
+    self.user_state = GraphState {
+      compiler: file.compiler,
+      gates: file.gates.into_iter().collect(),
+      outs_to_regs: file.outs_to_regs.into_iter().collect(),
+      outs_to_buses: file.outs_to_buses.into_iter().collect(),
+      immediates: file.immediates.into_iter().collect(),
+      bus_immediates: file.bus_immediates.into_iter().collect(),
+      clocks: file.clocks.into_iter().collect(),
+      connections: self.state.graph.connections.len(),
+      ..Default::default()
+    };
+    self.user_state.regs_to_outs = self
+      .user_state
+      .outs_to_regs
+      .iter()
+      .map(|(id, reg)| (*reg, *id))
+      .collect();
+
+    // The maps are up to date with the graph, so we only need to compile
+    self.user_state.simulate()?;
+    Ok(())
+  }
 }
 
 impl eframe::App for NodeGraphExample {
@@ -377,8 +838,102 @@ impl eframe::App for NodeGraphExample {
     egui::TopBottomPanel::top("top").show(ctx, |ui| {
       egui::menu::bar(ui, |ui| {
         egui::widgets::global_dark_light_mode_switch(ui);
+        ui.separator();
+
+        ui.add(egui::TextEdit::singleline(&mut self.path).desired_width(200.0));
+        if ui.button("Save").clicked() {
+          self.file_error = self.save_file().err().map(|err| err.to_string());
+        }
+        if ui.button("Open").clicked() {
+          self.file_error = self.open_file().err().map(|err| err.to_string());
+        }
+
+        ui.separator();
+        if ui.button("Step").clicked() {
+          self.user_state.step();
+        }
+        if ui.button("Run").clicked() {
+          self.user_state.pc = None;
+          self.user_state.compile_error =
+            self.user_state.simulate().err().map(|err| err.to_string());
+        }
+        if ui.button("Reset").clicked() {
+          self.user_state.pc = Some(0);
+          self.user_state.compile_error =
+            self.user_state.simulate().err().map(|err| err.to_string());
+        }
+        if let Some(pc) = self.user_state.pc {
+          let op = match self.user_state.simulation.ops.get(pc) {
+            Some(op) => op.to_string(),
+            None => "no ops".into(),
+          };
+          ui.label(format!(
+            "op {}/{}: {}",
+            pc,
+            self.user_state.simulation.op_count(),
+            op
+          ));
+        }
+
+        if let Some(err) = &self.file_error {
+          ui.colored_label(egui::Color32::RED, err.as_str());
+        }
+        if let Some(err) = &self.user_state.compile_error {
+          ui.colored_label(egui::Color32::RED, err.as_str());
+        }
       });
     });
+    if let Some(node) = self
+      .user_state
+      .active_node
+      .filter(|node| self.state.graph.nodes.contains_key(*node))
+    {
+      egui::SidePanel::right("truth_table").show(ctx, |ui| {
+        ui.heading("Truth Table");
+
+        // Sweeping every input is slow, so the table is only swept again
+        // after a recompile or for another node
+        if !matches!(
+          &self.user_state.truth_table,
+          Some((cached, _)) if *cached == node
+        ) {
+          let table = truth_table(&self.state.graph, node, &self.user_state);
+          self.user_state.truth_table = Some((node, table));
+        }
+        let Some((_, table)) = &self.user_state.truth_table else {
+          return;
+        };
+
+        let Some(rows) = &table.rows else {
+          ui.colored_label(
+            egui::Color32::YELLOW,
+            format!(
+              "{} inputs is too many, the truth table is limited to {}",
+              table.inputs.len(),
+              MAX_TRUTH_TABLE_INPUTS
+            ),
+          );
+          return;
+        };
+
+        egui::Grid::new("truth_table_grid")
+          .striped(true)
+          .show(ui, |ui| {
+            for (name, _) in table.inputs.iter().chain(table.outputs.iter()) {
+              ui.strong(name);
+            }
+            ui.end_row();
+
+            for (ins, outs) in rows {
+              for value in ins.iter().chain(outs) {
+                ui.label(if *value { "1" } else { "0" });
+              }
+              ui.end_row();
+            }
+          });
+      });
+    }
+
     let graph_response = egui::CentralPanel::default()
       .show(ctx, |ui| {
         self.state.draw_graph_editor(
@@ -391,6 +946,7 @@ impl eframe::App for NodeGraphExample {
       .inner;
 
     let mut changed = false;
+    let mut rewired = false;
 
     // If the graph has changed, we need to update our internal state
     //
@@ -401,41 +957,74 @@ impl eframe::App for NodeGraphExample {
     let new_connection_count = self.state.graph.connections.len();
     if new_connection_count != self.user_state.connections {
       changed = true;
+      rewired = true;
       self.user_state.connections = new_connection_count;
 
       // Clear the gates
       self.user_state.gates.clear();
-      self.user_state.outs_to_regs.clear();
+      // Each bit of a bus is hinted apart, scalars are hinted as bit 0
+      let buses = self.user_state.outs_to_buses.drain();
+      let bits = buses.flat_map(|(id, regs)| {
+        regs
+          .into_iter()
+          .enumerate()
+          .map(move |(bit, reg)| ((id, bit), reg))
+      });
+      self.user_state.reg_hint = self
+        .user_state
+        .outs_to_regs
+        .drain()
+        .map(|(id, reg)| ((id, 0), reg))
+        .chain(bits)
+        .collect();
       self.user_state.regs_to_outs.clear();
       self.user_state.compiler.immediate_count = 0;
 
       // Run through all immediates first since they are the first in the register stack
       for node in self.state.graph.nodes.iter().filter(|node| {
-        matches!(node.1.user_data.template, NodeTempl::Immediate)
+        matches!(
+          node.1.user_data.template,
+          NodeTempl::Immediate
+            | NodeTempl::BusImmediate { .. }
+            | NodeTempl::Clock
+        )
       }) {
         let (_, data) = node;
 
         let mut out_ids = data.output_ids();
         let out_id = out_ids.next().unwrap();
 
-        self
-          .user_state
-          .outs_to_regs
-          .insert(out_id, self.user_state.compiler.immediate_count);
-        self.user_state.compiler.immediate_count += 1;
+        // Bus immediates take an immediate for each bit
+        let start = self.user_state.compiler.immediate_count;
+        if let NodeTempl::BusImmediate { width } = data.user_data.template {
+          self
+            .user_state
+            .outs_to_buses
+            .insert(out_id, (start..start + width).collect());
+          self.user_state.compiler.immediate_count += width;
+        } else {
+          self.user_state.outs_to_regs.insert(out_id, start);
+          self.user_state.compiler.immediate_count += 1;
+        }
       }
 
-      // Reset the incrementer since we are recompiling
-      self.user_state.compiler.reset_incrementer();
+      // Reset the incrementer since we are recompiling, keeping the
+      // registers of the outputs that are still around
+      self
+        .user_state
+        .compiler
+        .reset_incrementer_hinted(&self.user_state.reg_hint);
 
       // Run through all nodes (except immediates) and add them to the simulation
-      for node in self
-        .state
-        .graph
-        .nodes
-        .iter()
-        .filter(|node| matches!(node.1.user_data.template, NodeTempl::And))
-      {
+      for node in self.state.graph.nodes.iter().filter(|node| {
+        matches!(
+          node.1.user_data.template,
+          NodeTempl::And
+            | NodeTempl::Not
+            | NodeTempl::FourBitAdder
+            | NodeTempl::Output
+        )
+      }) {
         let (id, data) = node;
         let template = data.user_data.template;
 
@@ -454,51 +1043,142 @@ impl eframe::App for NodeGraphExample {
             let a_out = a_out.unwrap();
             let b_out = b_out.unwrap();
 
-            let a = match self.user_state.outs_to_regs.get(&a_out) {
-              Some(reg) => *reg,
-              None => {
-                let reg = self.user_state.compiler.alloc();
-                self.user_state.outs_to_regs.insert(a_out, reg);
-                reg
-              }
-            };
-            let b = match self.user_state.outs_to_regs.get(&b_out) {
-              Some(reg) => *reg,
-              None => {
-                let reg = self.user_state.compiler.alloc();
-                self.user_state.outs_to_regs.insert(b_out, reg);
-                reg
-              }
-            };
-
-            let out_id = out_ids.next().unwrap();
-            let out = match self.user_state.outs_to_regs.get(&out_id) {
-              Some(reg) => *reg,
-              None => {
-                let reg = self.user_state.compiler.alloc();
-                self.user_state.outs_to_regs.insert(out_id, reg);
-                reg
-              }
-            };
+            let a = self.user_state.output_register(a_out);
+            let b = self.user_state.output_register(b_out);
+            let out = self.user_state.output_register(out_ids.next().unwrap());
 
             let gate = And { a, b, out };
             self.user_state.gates.insert(id, Gate::from(gate));
           }
+          NodeTempl::Not => {
+            let mut in_ids = data.input_ids();
+            let mut out_ids = data.output_ids();
+
+            let a_out = self.state.graph.connection(in_ids.next().unwrap());
+
+            if a_out.is_none() {
+              continue;
+            }
+
+            let a_out = a_out.unwrap();
+
+            let a = self.user_state.output_register(a_out);
+            let out = self.user_state.output_register(out_ids.next().unwrap());
+
+            let gate = Not { a, out };
+            self.user_state.gates.insert(id, Gate::from(gate));
+          }
+          NodeTempl::FourBitAdder => {
+            let mut in_ids = data.input_ids();
+            let mut out_ids = data.output_ids();
+
+            let a_out = self.state.graph.connection(in_ids.next().unwrap());
+            let b_out = self.state.graph.connection(in_ids.next().unwrap());
+
+            if a_out.is_none() || b_out.is_none() {
+              continue;
+            }
+
+            let a = self.user_state.output_bus(a_out.unwrap(), 4);
+            let b = self.user_state.output_bus(b_out.unwrap(), 4);
+            let s = self.user_state.output_bus(out_ids.next().unwrap(), 4);
+            let cout = self.user_state.output_register(out_ids.next().unwrap());
+
+            let gate = FourBitAdder {
+              a1: a[0],
+              a2: a[1],
+              a3: a[2],
+              a4: a[3],
+              b1: b[0],
+              b2: b[1],
+              b3: b[2],
+              b4: b[3],
+              s1: s[0],
+              s2: s[1],
+              s3: s[2],
+              s4: s[3],
+              cout,
+            };
+            self.user_state.gates.insert(id, Gate::from(gate));
+          }
+          NodeTempl::Output => {
+            let mut in_ids = data.input_ids();
+
+            // Outputs don't create a gate, but we track the register
+            // they display so it is part of the simulation
+            if let Some(in_out) =
+              self.state.graph.connection(in_ids.next().unwrap())
+            {
+              self.user_state.output_register(in_out);
+            }
+          }
 
           // TODO: Implement
-          NodeTempl::Immediate => {}
+          NodeTempl::Immediate
+          | NodeTempl::BusImmediate { .. }
+          | NodeTempl::Clock => {}
         }
       }
     }
 
     // Capture the values of all of the immediates
-    for node in
-      self.state.graph.nodes.iter().filter(|node| {
-        matches!(node.1.user_data.template, NodeTempl::Immediate)
-      })
-    {
+    let mut has_clock = false;
+    let mut immediate_error = None;
+    for node in self.state.graph.nodes.iter().filter(|node| {
+      matches!(
+        node.1.user_data.template,
+        NodeTempl::Immediate
+          | NodeTempl::BusImmediate { .. }
+          | NodeTempl::Clock
+      )
+    }) {
       let (id, data) = node;
 
+      // Bus immediates split their number into a bit for each register,
+      // driving the bus low if the number doesn't fit
+      if let NodeTempl::BusImmediate { width } = data.user_data.template {
+        let out_id = data.output_ids().next().unwrap();
+        let Some(regs) = self.user_state.outs_to_buses.get(&out_id) else {
+          continue;
+        };
+
+        let bits = data
+          .get_input("value")
+          .map_err(anyhow::Error::from)
+          .and_then(|in_id| self.state.graph[in_id].value.try_to_bits())
+          .unwrap_or_else(|err| {
+            immediate_error = Some(err.to_string());
+            vec![false; width]
+          });
+        let bits: Vec<(usize, bool)> = regs.iter().copied().zip(bits).collect();
+
+        if self.user_state.bus_immediates.get(&out_id) != Some(&bits) {
+          changed = true;
+        }
+        self.user_state.bus_immediates.insert(out_id, bits);
+        continue;
+      }
+
+      // Clocks count the frames and flip once they reach their period.
+      // The flip is picked up below like any other immediate changing.
+      if let NodeTempl::Clock = data.user_data.template {
+        has_clock = true;
+
+        let period = data
+          .get_input("frames")
+          .ok()
+          .and_then(|in_id| self.state.graph[in_id].value.try_to_number().ok())
+          .unwrap_or(1);
+
+        let (frames, value) =
+          self.user_state.clocks.entry(id).or_insert((0, false));
+        *frames += 1;
+        if *frames >= period {
+          *frames = 0;
+          *value = !*value;
+        }
+      }
+
       let mut out_ids = data.output_ids();
       let out_id = out_ids.next().unwrap();
 
@@ -526,46 +1206,38 @@ impl eframe::App for NodeGraphExample {
       }
     }
 
-    if changed {
-      // println!();
-      // println!("Gates: {:?}", self.user_state.gates);
-      // println!("Immediates: {:?}", self.user_state.immediates);
-      // println!("Regs: {:?}", self.user_state.outs_to_regs);
-
-      self.user_state.simulation = self
-        .user_state
-        .compiler
-        .compile(self.user_state.gates.values().collect::<Vec<_>>());
-
-      // println!("Compiler: {:?}", self.user_state.compiler);
-      // println!("Simulation: {:?}", self.user_state.simulation);
-
-      let mut immediates: Vec<bool> =
-        vec![false; self.user_state.compiler.immediate_count];
-
-      self
-        .user_state
-        .immediates
-        .iter()
-        .for_each(|(_, (index, val))| {
-          immediates[*index] = *val;
-        });
+    // Clocks need to keep ticking even when there is no input
+    if has_clock {
+      ctx.request_repaint();
+    }
 
-      self.user_state.simulation.run(&immediates);
-      // println!("Ran: {:?}", self.user_state.simulation);
+    // While stepping, changed immediates are picked up when stepping reaches
+    // the first op again, so only rewiring recompiles (and starts stepping
+    // over)
+    if changed && (rewired || self.user_state.pc.is_none()) {
+      self.user_state.compile_error =
+        self.user_state.simulate().err().map(|err| err.to_string());
+    }
+    if immediate_error.is_some() {
+      self.user_state.compile_error = immediate_error;
     }
 
     for node_response in graph_response.node_responses {
       // Here, we ignore all other graph events. But you may find
       // some use for them. For example, by playing a sound when a new
       // connection is created
-      if let NodeResponse::User(user_event) = node_response {
-        match user_event {
-          MyResponse::SetActiveNode(node) => {
-            self.user_state.active_node = Some(node)
-          }
-          MyResponse::ClearActiveNode => self.user_state.active_node = None,
+      match node_response {
+        NodeResponse::User(MyResponse::SetActiveNode(node)) => {
+          self.user_state.active_node = Some(node)
+        }
+        NodeResponse::User(MyResponse::ClearActiveNode) => {
+          self.user_state.active_node = None
         }
+        // A deleted clock would otherwise keep its state forever
+        NodeResponse::DeleteNodeFull { node_id, .. } => {
+          self.user_state.clocks.remove(&node_id);
+        }
+        _ => {}
       }
     }
 
@@ -594,6 +1266,96 @@ impl eframe::App for NodeGraphExample {
   }
 }
 
+/// The most inputs the truth table sweeps, since it doubles with each input
+const MAX_TRUTH_TABLE_INPUTS: usize = 8;
+
+/// Builds the truth table of a node, sweeping its rows if it has few enough
+/// inputs
+pub fn truth_table(
+  graph: &MyGraph,
+  node_id: NodeId,
+  user_state: &GraphState,
+) -> TruthTable {
+  let (inputs, outputs) = truth_table_columns(graph, node_id, user_state);
+  let rows = (inputs.len() <= MAX_TRUTH_TABLE_INPUTS).then(|| {
+    user_state.simulation.truth_table(
+      &inputs.iter().map(|(_, reg)| *reg).collect::<Vec<_>>(),
+      &outputs.iter().map(|(_, reg)| *reg).collect::<Vec<_>>(),
+    )
+  });
+
+  TruthTable {
+    inputs,
+    outputs,
+    rows,
+  }
+}
+
+/// Gets the named registers of the immediates a node depends on and of the
+/// node's outputs, which are the columns of its truth table
+pub fn truth_table_columns(
+  graph: &MyGraph,
+  node_id: NodeId,
+  user_state: &GraphState,
+) -> (Vec<(String, usize)>, Vec<(String, usize)>) {
+  let column = |out_id: OutputId, name: String| {
+    user_state
+      .outs_to_regs
+      .get(&out_id)
+      .map(|reg| (format!("{} r{}", name, reg), *reg))
+  };
+
+  // Walk back through the connections to find the immediates
+  let mut inputs = vec![];
+  let mut visited = HashSet::from([node_id]);
+  let mut queue = vec![node_id];
+  while let Some(node_id) = queue.pop() {
+    let node = &graph[node_id];
+    if let NodeTempl::Immediate | NodeTempl::Clock = node.user_data.template {
+      let out_id = node.output_ids().next().unwrap();
+      inputs.extend(column(out_id, node.label.clone()));
+    }
+    if let NodeTempl::BusImmediate { .. } = node.user_data.template {
+      let out_id = node.output_ids().next().unwrap();
+      if let Some(regs) = user_state.outs_to_buses.get(&out_id) {
+        inputs.extend(
+          regs
+            .iter()
+            .enumerate()
+            .map(|(i, reg)| (format!("{}[{}] r{}", node.label, i, reg), *reg)),
+        );
+      }
+    }
+
+    for in_id in node.input_ids() {
+      if let Some(out_id) = graph.connection(in_id) {
+        let other = graph[out_id].node;
+        if visited.insert(other) {
+          queue.push(other);
+        }
+      }
+    }
+  }
+  inputs.sort_by_key(|(_, reg)| *reg);
+
+  // Outputs show whatever is connected to them
+  let node = &graph[node_id];
+  let outputs = match node.user_data.template {
+    NodeTempl::Output => node
+      .input_ids()
+      .filter_map(|in_id| graph.connection(in_id))
+      .filter_map(|out_id| column(out_id, node.label.clone()))
+      .collect(),
+    _ => node
+      .outputs
+      .iter()
+      .filter_map(|(name, out_id)| column(*out_id, name.clone()))
+      .collect(),
+  };
+
+  (inputs, outputs)
+}
+
 type OutputsCache = HashMap<OutputId, ValueType>;
 
 /// Recursively evaluates all dependencies of this node, then evaluates the node itself.
@@ -660,6 +1422,14 @@ pub fn evaluate_node(
       // the graphs, you can come up with your own evaluation semantics!
       populate_output(self.graph, self.outputs_cache, self.node_id, name, value)
     }
+    fn populate_outputs(
+      &mut self,
+      values: Vec<(OutputId, bool)>,
+    ) -> anyhow::Result<ValueType> {
+      // Nodes with several outputs populate all of them and return the
+      // first one as the "final" output
+      populate_outputs(self.outputs_cache, values)
+    }
     fn input_scalar(
       &mut self,
       name: &str,
@@ -679,22 +1449,40 @@ pub fn evaluate_node(
   let node = &graph[node_id];
   let mut evaluator = Evaluator::new(graph, outputs_cache, node_id);
   match node.user_data.template {
-    NodeTempl::And => {
-      let mut outs = node.output_ids();
-      let out_id = outs.next().unwrap();
-
-      let value = if let Some(reg) = user_state.outs_to_regs.get(&out_id) {
-        user_state.simulation.register(*reg)
-      } else {
-        false
-      };
+    // Gates read their result straight from the simulation register, so a
+    // Not node reports the inverted value computed by the simulation
+    NodeTempl::And | NodeTempl::Not => {
+      let values = user_state.output_values(node.output_ids());
+      evaluator.populate_outputs(values)
+    }
+    NodeTempl::FourBitAdder => {
+      let cout = user_state.output_values(node.get_output("cout").into_iter());
+      evaluator.populate_outputs(cout)?;
 
-      evaluator.output_scalar("out", value)
+      let sum = node.get_output("sum")?;
+      let value = user_state.bus_value(sum);
+      evaluator.populate_output("sum", ValueType::Bus { width: 4, value })
     }
     NodeTempl::Immediate => {
       let a = evaluator.input_scalar("A", user_state)?;
       evaluator.output_scalar("out", a)
     }
+    NodeTempl::BusImmediate { .. } => {
+      let value = evaluator.evaluate_input("value", user_state)?;
+      evaluator.populate_output("out", value)
+    }
+    NodeTempl::Clock => {
+      let value = user_state
+        .clocks
+        .get(&node_id)
+        .map(|(_, value)| *value)
+        .unwrap_or(false);
+      evaluator.output_scalar("out", value)
+    }
+    NodeTempl::Output => {
+      let value = evaluator.input_scalar("in", user_state)?;
+      Ok(ValueType::Scalar { value })
+    }
   }
 }
 
@@ -710,6 +1498,20 @@ fn populate_output(
   Ok(value)
 }
 
+fn populate_outputs(
+  outputs_cache: &mut OutputsCache,
+  values: Vec<(OutputId, bool)>,
+) -> anyhow::Result<ValueType> {
+  let mut first = None;
+  for (output_id, value) in values {
+    let value = ValueType::Scalar { value };
+    outputs_cache.insert(output_id, value);
+    first.get_or_insert(value);
+  }
+
+  first.ok_or_else(|| anyhow::anyhow!("Node has no outputs"))
+}
+
 // Evaluates the input value of
 fn evaluate_input(
   graph: &MyGraph,
@@ -751,3 +1553,246 @@ fn evaluate_input(
     Ok(graph[input_id].value)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  /// Test that outputs keep their register and new outputs get fresh ones
+  fn output_register_allocation() {
+    let mut graph = MyGraph::new();
+    let mut user_state = GraphState {
+      compiler: Compiler::new(1),
+      ..Default::default()
+    };
+
+    let node = graph.add_node(
+      "Not Gate".into(),
+      NodeData {
+        template: NodeTempl::Not,
+      },
+      |_, _| {},
+    );
+    let a = graph.add_output_param(node, "a".into(), DataType::Scalar);
+    let b = graph.add_output_param(node, "b".into(), DataType::Scalar);
+
+    // The first register after the immediate is allocated
+    assert_eq!(user_state.output_register(a), 1);
+    assert_eq!(user_state.output_register(b), 2);
+
+    // Asking again returns the same register instead of allocating
+    assert_eq!(user_state.output_register(a), 1);
+    assert_eq!(user_state.outs_to_regs.len(), 2);
+  }
+
+  #[test]
+  /// Test that every output of a node is read from its own register
+  fn multi_output_values() {
+    let mut graph = MyGraph::new();
+    let mut user_state = GraphState {
+      compiler: Compiler::new(1),
+      ..Default::default()
+    };
+
+    let node = graph.add_node(
+      "Not Gate".into(),
+      NodeData {
+        template: NodeTempl::Not,
+      },
+      |_, _| {},
+    );
+    let a = graph.add_output_param(node, "a".into(), DataType::Scalar);
+    let b = graph.add_output_param(node, "b".into(), DataType::Scalar);
+    let c = graph.add_output_param(node, "c".into(), DataType::Scalar);
+
+    // Drive the first two outputs with a chain of Nots, leaving the third
+    // without a register
+    let out_a = user_state.output_register(a);
+    let out_b = user_state.output_register(b);
+    user_state.simulation = user_state
+      .compiler
+      .compile(vec![
+        &Gate::from(Not { a: 0, out: out_a }),
+        &Gate::from(Not {
+          a: out_a,
+          out: out_b,
+        }),
+      ])
+      .unwrap();
+    user_state.simulation.run(&[false]);
+
+    let values = user_state.output_values(graph[node].output_ids());
+    assert_eq!(values, vec![(a, true), (b, false), (c, false)]);
+
+    let mut cache = OutputsCache::new();
+    let first = populate_outputs(&mut cache, values).unwrap();
+    assert!(first.try_to_scalar().unwrap());
+    assert_eq!(cache.len(), 3);
+    assert!(!cache[&b].try_to_scalar().unwrap());
+  }
+
+  #[test]
+  /// Test that the truth table of an output has the immediates behind it as
+  /// inputs
+  fn truth_table_columns_from_graph() {
+    let mut graph = MyGraph::new();
+    let mut user_state = GraphState {
+      compiler: Compiler::new(3),
+      ..Default::default()
+    };
+
+    let mut add_node = |label: &str, template: NodeTempl| {
+      graph.add_node(label.into(), NodeData { template }, |_, _| {})
+    };
+    let a = add_node("A", NodeTempl::Immediate);
+    let b = add_node("B", NodeTempl::Immediate);
+    let unused = add_node("C", NodeTempl::Immediate);
+    let not = add_node("Not", NodeTempl::Not);
+    let output = add_node("Output", NodeTempl::Output);
+
+    let [a_out, b_out, unused_out, not_out] = [a, b, unused, not]
+      .map(|node| graph.add_output_param(node, "out".into(), DataType::Scalar));
+    let mut add_input = |node: NodeId, name: &str| {
+      graph.add_input_param(
+        node,
+        name.into(),
+        DataType::Scalar,
+        ValueType::Scalar { value: false },
+        InputParamKind::ConnectionOnly,
+        true,
+      )
+    };
+    let not_in = add_input(not, "A");
+    let output_in = add_input(output, "in");
+    graph.add_connection(b_out, not_in);
+    graph.add_connection(not_out, output_in);
+
+    for (reg, out_id) in [a_out, b_out, unused_out].into_iter().enumerate() {
+      user_state.outs_to_regs.insert(out_id, reg);
+    }
+    let not_reg = user_state.output_register(not_out);
+
+    let (inputs, outputs) = truth_table_columns(&graph, output, &user_state);
+    assert_eq!(inputs, vec![("B r1".to_string(), 1)]);
+    assert_eq!(outputs, vec![(format!("Output r{}", not_reg), not_reg)]);
+
+    let (inputs, outputs) = truth_table_columns(&graph, not, &user_state);
+    assert_eq!(inputs, vec![("B r1".to_string(), 1)]);
+    assert_eq!(outputs, vec![(format!("out r{}", not_reg), not_reg)]);
+  }
+
+  #[test]
+  /// Test that recompiling drops the cached truth table
+  fn simulate_clears_truth_table() {
+    let mut graph = MyGraph::new();
+    let mut user_state = GraphState {
+      compiler: Compiler::new(1),
+      ..Default::default()
+    };
+
+    let node = graph.add_node(
+      "A".into(),
+      NodeData {
+        template: NodeTempl::Immediate,
+      },
+      |_, _| {},
+    );
+    let out_id = graph.add_output_param(node, "out".into(), DataType::Scalar);
+    user_state.outs_to_regs.insert(out_id, 0);
+    user_state.simulate().unwrap();
+
+    let table = truth_table(&graph, node, &user_state);
+    assert_eq!(table.inputs, vec![("A r0".to_string(), 0)]);
+    assert_eq!(
+      table.rows,
+      Some(vec![(vec![false], vec![false]), (vec![true], vec![true])])
+    );
+
+    user_state.truth_table = Some((node, table));
+    user_state.simulate().unwrap();
+    assert!(user_state.truth_table.is_none());
+  }
+
+  #[test]
+  /// Test that buses only connect to buses of the same width
+  fn bus_widths_must_match() {
+    assert_eq!(DataType::Bus { width: 4 }, DataType::Bus { width: 4 });
+    assert_ne!(DataType::Bus { width: 4 }, DataType::Bus { width: 8 });
+    assert_ne!(DataType::Bus { width: 1 }, DataType::Scalar);
+  }
+
+  #[test]
+  /// Test that a bus gets a register per bit and reads back as a number
+  fn bus_registers_and_value() {
+    let mut graph = MyGraph::new();
+    let mut user_state = GraphState {
+      compiler: Compiler::new(8),
+      ..Default::default()
+    };
+
+    let node = graph.add_node(
+      "4-Bit Adder".into(),
+      NodeData {
+        template: NodeTempl::FourBitAdder,
+      },
+      |_, _| {},
+    );
+    let [a, b, sum] = ["a", "b", "sum"].map(|name| {
+      graph.add_output_param(node, name.into(), DataType::Bus { width: 4 })
+    });
+    let cout = graph.add_output_param(node, "cout".into(), DataType::Scalar);
+
+    // The inputs are the immediates and the sum is allocated after them
+    user_state.outs_to_buses.insert(a, vec![0, 1, 2, 3]);
+    user_state.outs_to_buses.insert(b, vec![4, 5, 6, 7]);
+    assert_eq!(user_state.output_bus(sum, 4), vec![8, 9, 10, 11]);
+    assert_eq!(user_state.output_bus(sum, 4), vec![8, 9, 10, 11]);
+    let carry = user_state.output_register(cout);
+
+    user_state.simulation = user_state
+      .compiler
+      .compile(vec![&Gate::from(FourBitAdder {
+        a1: 0,
+        a2: 1,
+        a3: 2,
+        a4: 3,
+        b1: 4,
+        b2: 5,
+        b3: 6,
+        b4: 7,
+        s1: 8,
+        s2: 9,
+        s3: 10,
+        s4: 11,
+        cout: carry,
+      })])
+      .unwrap();
+
+    // 11 + 7 = 18, which is 2 with a carry
+    let bits = |value: u32| (0..4).map(move |i| value >> i & 1 == 1);
+    let immediates: Vec<bool> = bits(11).chain(bits(7)).collect();
+    user_state.simulation.run(&immediates);
+
+    assert_eq!(user_state.bus_value(a), 11);
+    assert_eq!(user_state.bus_value(sum), 2);
+    assert!(user_state.simulation.register(carry));
+  }
+
+  #[test]
+  /// Test that a bus immediate splits its number into bits that fit
+  fn bus_immediate_bits() {
+    let bits = |width, value| ValueType::Bus { width, value }.try_to_bits();
+
+    assert_eq!(bits(4, 11).unwrap(), vec![true, true, false, true]);
+    assert_eq!(
+      bits(8, 1).unwrap(),
+      vec![true, false, false, false, false, false, false, false]
+    );
+    assert_eq!(bits(4, 15).unwrap(), vec![true; 4]);
+
+    // 16 needs a fifth bit
+    assert!(bits(4, 16).is_err());
+    assert!(ValueType::Scalar { value: true }.try_to_bits().is_err());
+  }
+}
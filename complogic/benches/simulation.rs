@@ -0,0 +1,85 @@
+use complogic::{
+  to_bits, Compiler, FourBitAdder, Gate, OverflowMode, RippleCarryAdder,
+};
+use criterion::{
+  black_box, criterion_group, criterion_main, BenchmarkId, Criterion,
+  Throughput,
+};
+
+/// Creates a ripple carry adder of `width` bits, which chains a full adder
+/// for each bit, on the immediates of a new compiler
+fn adder(width: usize) -> (Compiler, Gate) {
+  let mut compiler = Compiler::new(2 * width);
+  let adder = RippleCarryAdder {
+    a: (0..width).collect(),
+    b: (width..2 * width).collect(),
+    sum: (0..width).map(|_| compiler.alloc()).collect(),
+    cout: compiler.alloc(),
+    overflow: OverflowMode::Wrap,
+  };
+
+  (compiler, Gate::from(adder))
+}
+
+fn compile(c: &mut Criterion) {
+  let mut group = c.benchmark_group("compile");
+  for width in [4, 16, 64, 256] {
+    let (compiler, gate) = adder(width);
+    group.throughput(Throughput::Elements(gate.cost() as u64));
+    group.bench_with_input(
+      BenchmarkId::from_parameter(width),
+      &gate,
+      |b, gate| b.iter(|| compiler.clone().compile(vec![gate]).unwrap()),
+    );
+  }
+  group.finish();
+}
+
+fn run(c: &mut Criterion) {
+  let mut group = c.benchmark_group("run");
+  for width in [4, 16, 64, 256] {
+    let (mut compiler, gate) = adder(width);
+    let mut simulation = compiler.compile(vec![&gate]).unwrap();
+    let immediates: Vec<bool> = (0..2 * width).map(|i| i % 3 == 0).collect();
+
+    group.throughput(Throughput::Elements(simulation.op_count() as u64));
+    group.bench_with_input(
+      BenchmarkId::from_parameter(width),
+      &immediates,
+      |b, immediates| b.iter(|| simulation.run(black_box(immediates))),
+    );
+  }
+  group.finish();
+}
+
+fn four_bit_adder_sweep(c: &mut Criterion) {
+  let mut compiler = Compiler::new(8);
+  let adder = FourBitAdder {
+    a1: 0,
+    a2: 1,
+    a3: 2,
+    a4: 3,
+    b1: 4,
+    b2: 5,
+    b3: 6,
+    b4: 7,
+    s1: compiler.alloc(),
+    s2: compiler.alloc(),
+    s3: compiler.alloc(),
+    s4: compiler.alloc(),
+    cout: compiler.alloc(),
+  };
+  let mut simulation = compiler.compile(vec![&Gate::from(adder)]).unwrap();
+  let inputs: Vec<Vec<bool>> = (0..1 << 8).map(|i| to_bits(i, 8)).collect();
+
+  c.bench_function("four_bit_adder_sweep", |b| {
+    b.iter(|| {
+      for immediates in inputs.iter() {
+        simulation.run(black_box(immediates));
+      }
+    })
+  });
+}
+
+criterion_group!(benches, compile, run, four_bit_adder_sweep);
+criterion_main!(benches);
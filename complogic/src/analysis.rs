@@ -0,0 +1,483 @@
+use std::{
+  collections::{HashMap, HashSet},
+  mem,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  normalize_op, op_inputs, op_output, to_bits, Compiler, Op, Simulation,
+};
+
+/// A register driven by a Nand whose inputs are reached from the same source
+/// through paths of different depths, which can glitch while the slower path
+/// catches up
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HazardReport {
+  /// The output of the Nand where the paths reconverge
+  pub output: usize,
+
+  /// The register the paths start from
+  pub source: usize,
+
+  /// The longest depth of the paths into each input of the Nand, in ops
+  pub depths: (usize, usize),
+}
+
+impl Compiler {
+  /// Gets the outputs of the ops of the last compile that read the register
+  pub fn fanout(&self, reg: usize) -> Vec<usize> {
+    self
+      .ops
+      .iter()
+      .filter(|op| op_inputs(**op).contains(&reg))
+      .map(|op| op_output(*op))
+      .collect()
+  }
+
+  /// Gets the registers read by the op of the last compile that writes to the
+  /// register
+  pub fn fanin(&self, reg: usize) -> Vec<usize> {
+    self
+      .ops
+      .iter()
+      .find(|op| !matches!(op, Op::Set(..)) && op_output(**op) == reg)
+      .map(|op| op_inputs(*op))
+      .unwrap_or_default()
+  }
+
+  /// Finds static hazard candidates in the ops of the last compile
+  ///
+  /// Ops are walked in their scheduled order, so the back edges of loops
+  /// aren't followed.
+  pub fn find_hazards(&self) -> Vec<HazardReport> {
+    // The longest depth each register is reached at from each source
+    let mut depths: HashMap<usize, HashMap<usize, usize>> = HashMap::new();
+    let mut reports = vec![];
+
+    for op in self.layers.iter().flatten() {
+      match *op {
+        Op::Set(reg, _) => {
+          depths.insert(reg, HashMap::from([(reg, 0)]));
+        }
+        op => {
+          let inputs: Vec<HashMap<usize, usize>> = op_inputs(op)
+            .into_iter()
+            .map(|input| {
+              depths
+                .get(&input)
+                .cloned()
+                .unwrap_or_else(|| HashMap::from([(input, 0)]))
+            })
+            .collect();
+          let out = op_output(op);
+
+          if inputs.len() == 2 {
+            let mut sources: Vec<&usize> = inputs[0]
+              .keys()
+              .filter(|source| inputs[1].contains_key(source))
+              .collect();
+            sources.sort();
+
+            for source in sources {
+              let depth_a = inputs[0][source];
+              let depth_b = inputs[1][source];
+
+              if depth_a != depth_b {
+                reports.push(HazardReport {
+                  output: out,
+                  source: *source,
+                  depths: (depth_a, depth_b),
+                });
+              }
+            }
+          }
+
+          let mut reached: HashMap<usize, usize> = HashMap::new();
+          for input in inputs {
+            for (source, depth) in input {
+              let longest = reached.entry(source).or_default();
+              *longest = (*longest).max(depth + 1);
+            }
+          }
+          depths.insert(out, reached);
+        }
+      }
+    }
+
+    reports
+  }
+
+  /// Finds the path through the ops of the last compile with the longest
+  /// propagation delay, returning its registers from start to end and its
+  /// total delay in ps
+  ///
+  /// Each op takes the delay of the op writing the same register in the
+  /// compiled simulation (see [`Simulation::delays`]), and ops without one
+  /// take 1ps like in [`Simulation::run_timed`]. Ops are walked in their
+  /// scheduled order, so the back edges of loops aren't followed.
+  pub fn critical_path(&self, simulation: &Simulation) -> (Vec<usize>, u32) {
+    let delays: HashMap<usize, u32> = simulation
+      .ops
+      .iter()
+      .zip(simulation.delays.iter())
+      .map(|(op, delay)| (op_output(*op), *delay))
+      .collect();
+
+    // The latest time each register settles and the input it waits on
+    let mut arrivals: HashMap<usize, (u32, Option<usize>)> = HashMap::new();
+    let mut end: Option<(usize, u32)> = None;
+
+    for op in self.layers.iter().flatten() {
+      let out = op_output(*op);
+      let delay = delays.get(&out).copied().unwrap_or(1);
+      let arrival = op_inputs(*op)
+        .into_iter()
+        .map(|input| (arrivals.get(&input).map_or(0, |(time, _)| *time), input))
+        .max_by_key(|(time, _)| *time)
+        .map_or((0, None), |(time, input)| (time + delay, Some(input)));
+
+      arrivals.insert(out, arrival);
+      if !matches!(end, Some((_, time)) if time >= arrival.0) {
+        end = Some((out, arrival.0));
+      }
+    }
+
+    let Some((mut reg, delay)) = end else {
+      return (vec![], 0);
+    };
+
+    let mut path = vec![reg];
+    while let Some((_, Some(input))) = arrivals.get(&reg) {
+      reg = *input;
+      path.push(reg);
+    }
+    path.reverse();
+
+    (path, delay)
+  }
+
+  /// Groups the gates of the last compile that lower to the same ops over
+  /// the same inputs, as indexes into the compiled gates
+  ///
+  /// The ops are normalized first (see [`normalize_op`]), so gates that only
+  /// differ by the order of their inputs are grouped.
+  ///
+  /// The gates of a group compute the same outputs, so all but one of them
+  /// can be dropped and their outputs tied to the one that's kept. Only
+  /// groups of more than one gate are returned. The initial values of
+  /// latches aren't compared.
+  pub fn find_equivalent_gates(&self) -> Vec<Vec<usize>> {
+    let mut classes: HashMap<Vec<_>, Vec<usize>> = HashMap::new();
+
+    for (index, (_, outputs)) in self.gate_groups.iter().enumerate() {
+      if outputs.is_empty() {
+        continue;
+      }
+
+      // Registers written by the gate are numbered in the order its ops use
+      // them, so two gates only differ by the registers they read from
+      let local: HashSet<usize> = outputs.iter().copied().collect();
+      let mut renamed: HashMap<usize, usize> = HashMap::new();
+      let mut net = |reg: usize| {
+        if local.contains(&reg) {
+          let next = renamed.len();
+          (true, *renamed.entry(reg).or_insert(next))
+        } else {
+          (false, reg)
+        }
+      };
+
+      let signature: Vec<_> = self
+        .ops
+        .iter()
+        .filter(|op| {
+          !matches!(op, Op::Set(..)) && local.contains(&op_output(**op))
+        })
+        .map(|op| {
+          let op = normalize_op(*op);
+          let regs: Vec<(bool, usize)> = op_inputs(op)
+            .into_iter()
+            .chain([op_output(op)])
+            .map(&mut net)
+            .collect();
+          (mem::discriminant(&op), regs)
+        })
+        .collect();
+      classes.entry(signature).or_default().push(index);
+    }
+
+    let mut classes: Vec<Vec<usize>> = classes
+      .into_values()
+      .filter(|class| class.len() > 1)
+      .collect();
+    classes.sort();
+    classes
+  }
+}
+
+/// Checks that two simulations agree on `outputs` for every combination of
+/// the immediates in `inputs`
+///
+/// Returns the values of `inputs` for the first combination where the
+/// outputs differ, or `None` if the simulations are equivalent.
+pub fn equivalent(
+  a: &mut Simulation,
+  b: &mut Simulation,
+  inputs: &[usize],
+  outputs: &[usize],
+) -> Option<Vec<bool>> {
+  let len = inputs.iter().max().map(|max| max + 1).unwrap_or(0);
+
+  for combination in 0..1usize << inputs.len() {
+    let values = to_bits(combination, inputs.len());
+
+    let mut immediates = vec![false; len];
+    for (input, value) in inputs.iter().zip(values.iter()) {
+      immediates[*input] = *value;
+    }
+
+    a.run(&immediates);
+    b.run(&immediates);
+
+    if outputs
+      .iter()
+      .any(|out| a.registers[*out] != b.registers[*out])
+    {
+      return Some(values);
+    }
+  }
+
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    synthesize, And, FourBitAdder, FullAdder, Gate, Nand, Not, Or, Xor,
+  };
+
+  #[test]
+  /// Test that identical Xors over the same inputs are grouped, but not gates
+  /// over other inputs or of other kinds
+  fn equivalent_gates() {
+    let mut compiler = Compiler::new(3);
+    let xor = |compiler: &mut Compiler, a, b| {
+      Gate::from(Xor {
+        a,
+        b,
+        out: compiler.alloc(),
+      })
+    };
+    let gates = [
+      xor(&mut compiler, 0, 1),
+      Gate::from(And {
+        a: 0,
+        b: 1,
+        out: compiler.alloc(),
+      }),
+      xor(&mut compiler, 0, 2),
+      xor(&mut compiler, 0, 1),
+    ];
+    compiler.compile(gates.iter().collect()).unwrap();
+
+    assert_eq!(compiler.find_equivalent_gates(), vec![vec![0, 3]]);
+  }
+
+  #[test]
+  /// Test that Nands with swapped inputs are grouped once normalized
+  fn equivalent_swapped_nands() {
+    assert_eq!(normalize_op(Op::Nand(1, 0, 2)), Op::Nand(0, 1, 2));
+    assert_eq!(normalize_op(Op::Nand(0, 1, 2)), Op::Nand(0, 1, 2));
+
+    let mut compiler = Compiler::new(2);
+    let x = Nand {
+      a: 1,
+      b: 0,
+      out: compiler.alloc(),
+    };
+    let y = Nand {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    compiler
+      .compile(vec![&Gate::from(x), &Gate::from(y)])
+      .unwrap();
+
+    assert_eq!(compiler.find_equivalent_gates(), vec![vec![0, 1]]);
+  }
+
+  #[test]
+  /// Test that Nand(a, Not(a)) is reported as a hazard
+  fn static_hazard() {
+    let mut compiler = Compiler::new(1);
+    let a = 0;
+
+    let not = Not {
+      a,
+      out: compiler.alloc(),
+    };
+    let nand = Nand {
+      a,
+      b: not.out,
+      out: compiler.alloc(),
+    };
+
+    compiler
+      .compile(vec![&Gate::from(not), &Gate::from(nand)])
+      .unwrap();
+    assert_eq!(
+      compiler.find_hazards(),
+      vec![HazardReport {
+        output: nand.out,
+        source: a,
+        depths: (0, 1),
+      }]
+    );
+  }
+
+  #[test]
+  /// Test that an And gate doesn't report any hazards
+  fn no_hazards() {
+    let mut compiler = Compiler::new(2);
+
+    let and = And {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+
+    compiler.compile(vec![&Gate::from(and)]).unwrap();
+    assert!(compiler.find_hazards().is_empty());
+  }
+
+  #[test]
+  /// Test the fan-out and fan-in of the full adder's first half adder sum
+  fn fanout_fanin_full_adder() {
+    let mut compiler = Compiler::new(3);
+    let full_adder = FullAdder {
+      a: 0,
+      b: 1,
+      cin: 2,
+      s: compiler.alloc(),
+      cout: compiler.alloc(),
+    };
+    compiler.compile(vec![&Gate::from(full_adder)]).unwrap();
+
+    // The first half adder's sum is the first internal register, which the
+    // second half adder's Xor reads twice and its And reads once
+    let sum = 5;
+    assert_eq!(compiler.fanout(sum).len(), 3);
+
+    // The sum comes out of the Not of the Xor's And
+    let fanin = compiler.fanin(sum);
+    assert_eq!(fanin.len(), 1);
+    assert_eq!(compiler.fanin(fanin[0]).len(), 2);
+
+    // Immediates aren't written by a Nand
+    assert!(compiler.fanin(0).is_empty());
+  }
+
+  /// Compiles a sum of minterms Xor with its output at register 2
+  fn synthesized_xor() -> Simulation {
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+
+    let rows = [
+      (vec![false, true], Some(true)),
+      (vec![true, false], Some(true)),
+    ];
+    let gates = synthesize(2, &rows, &mut compiler).unwrap();
+    if let Some(Gate::Or(or)) = gates.last() {
+      compiler.tie(out, or.out);
+    }
+
+    compiler.compile(gates.iter().collect()).unwrap()
+  }
+
+  #[test]
+  /// Test that a Xor gate and a synthesized Xor are equivalent
+  fn equivalent_xor() {
+    let mut compiler = Compiler::new(2);
+    let xor = Xor {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let mut gate = compiler.compile(vec![&Gate::from(xor)]).unwrap();
+
+    let mut synthesized = synthesized_xor();
+    assert_eq!(
+      equivalent(&mut gate, &mut synthesized, &[0, 1], &[xor.out]),
+      None
+    );
+  }
+
+  #[test]
+  /// Test that an Or gate isn't equivalent to a Xor
+  fn not_equivalent() {
+    let mut compiler = Compiler::new(2);
+    let or = Or {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let mut gate = compiler.compile(vec![&Gate::from(or)]).unwrap();
+
+    let mut synthesized = synthesized_xor();
+    assert_eq!(
+      equivalent(&mut gate, &mut synthesized, &[0, 1], &[or.out]),
+      Some(vec![true, true])
+    );
+  }
+
+  #[test]
+  /// Test that the critical path of a 4-bit adder runs along its carry chain
+  fn critical_path_carry_chain() {
+    let mut compiler = Compiler::new(8);
+    let adder = FourBitAdder {
+      a1: 0,
+      a2: 1,
+      a3: 2,
+      a4: 3,
+      b1: 4,
+      b2: 5,
+      b3: 6,
+      b4: 7,
+      s1: compiler.alloc(),
+      s2: compiler.alloc(),
+      s3: compiler.alloc(),
+      s4: compiler.alloc(),
+      cout: compiler.alloc(),
+    };
+    let mut simulation = compiler.compile(vec![&Gate::from(adder)]).unwrap();
+
+    // The first stage's carry takes 8ps from its inputs, through the Xor of
+    // the first half adder, the And of the second, and the carry's Or. Each
+    // later stage adds the 4ps from its carry in to its carry out.
+    let (path, delay) = compiler.critical_path(&simulation);
+    assert_eq!(delay, 8 + 3 * 4);
+    assert_eq!(path.len(), delay as usize + 1);
+    assert!(path[0] < 8, "{:?} doesn't start at an input", path);
+    assert!(path.contains(&adder.cout) || path.contains(&adder.s4));
+
+    // Each register on the path is read by the op that writes the next
+    for pair in path.windows(2) {
+      assert!(compiler.fanin(pair[1]).contains(&pair[0]), "{:?}", pair);
+    }
+
+    // Slowing down the ops on the path slows down the path
+    simulation.delays = simulation
+      .ops
+      .iter()
+      .map(|op| if path.contains(&op_output(*op)) { 3 } else { 1 })
+      .collect();
+    assert_eq!(compiler.critical_path(&simulation), (path, 3 * delay));
+
+    let mut empty = Compiler::new(1);
+    let simulation = empty.compile(vec![]).unwrap();
+    assert_eq!(empty.critical_path(&simulation), (vec![], 0));
+  }
+}
@@ -0,0 +1,83 @@
+//! Runs a saved circuit without the GUI and prints its named outputs
+//!
+//! Usage: `complogic-sim <circuit.json> [input=value]...`, where each input
+//! is the label of an immediate and each value is `0`, `1`, `true` or
+//! `false`. Inputs that aren't given are low.
+
+use std::{env, process::ExitCode};
+
+use complogic::CircuitFile;
+
+/// The most runs a sequential circuit is given to settle
+const MAX_RUNS: usize = 100;
+
+fn main() -> ExitCode {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let Some((path, inputs)) = args.split_first() else {
+    eprintln!("usage: complogic-sim <circuit.json> [input=value]...");
+    return ExitCode::from(2);
+  };
+
+  match run(path, inputs) {
+    Ok(outputs) => {
+      for (name, value) in outputs {
+        println!("{}={}", name, value as u8);
+      }
+
+      ExitCode::SUCCESS
+    }
+    Err(err) => {
+      eprintln!("error: {}", err);
+      ExitCode::FAILURE
+    }
+  }
+}
+
+/// Runs the circuit at `path` and gets the value of each labelled register
+/// that isn't an immediate, in register order
+fn run(path: &str, inputs: &[String]) -> Result<Vec<(String, bool)>, String> {
+  let file = CircuitFile::load(path).map_err(|err| err.to_string())?;
+
+  let mut immediates = vec![false; file.immediate_count];
+  for input in inputs.iter() {
+    let (name, value) = input
+      .split_once('=')
+      .ok_or_else(|| format!("expected input=value, found {}", input))?;
+    let value = match value {
+      "0" | "false" => false,
+      "1" | "true" => true,
+      _ => return Err(format!("invalid value {} for {}", value, name)),
+    };
+    let reg = file
+      .labels
+      .iter()
+      .find(|(reg, label)| **reg < file.immediate_count && *label == name)
+      .map(|(reg, _)| *reg)
+      .ok_or_else(|| format!("unknown input {}", name))?;
+
+    immediates[reg] = value;
+  }
+
+  let mut simulation = file
+    .compiler()
+    .compile(file.gates.iter().collect())
+    .map_err(|err| err.to_string())?;
+  if !simulation.run_until_stable(&immediates, MAX_RUNS) {
+    return Err(format!("the circuit didn't settle in {} runs", MAX_RUNS));
+  }
+
+  let mut outputs: Vec<(usize, &String)> = file
+    .labels
+    .iter()
+    .filter(|(reg, _)| **reg >= file.immediate_count)
+    .map(|(reg, label)| (*reg, label))
+    .collect();
+  outputs.sort();
+
+  Ok(
+    outputs
+      .into_iter()
+      .map(|(reg, label)| (label.clone(), simulation.registers[reg]))
+      .collect(),
+  )
+}
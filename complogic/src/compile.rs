@@ -1,7 +1,13 @@
-use std::{collections::HashSet, fs};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt,
+  hash::Hash,
+};
 
-use crate::{gates::Gate, Simulation};
-use petgraph::{dot::Dot, graph::DiGraph, stable_graph::NodeIndex, Direction};
+use crate::{gates::Gate, Simulation, ValidationError};
+use petgraph::{
+  algo::has_path_connecting, graph::DiGraph, stable_graph::NodeIndex, Direction,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -11,10 +17,118 @@ pub enum Op {
 
   /// Sets the value of the register at the given address
   Set(usize, bool),
+
+  /// Copies the value of the input address into the output address
+  Buf(usize, usize),
+
+  /// Stores the inverted value of the input address in the output address
+  Inv(usize, usize),
+
+  /// Performs an And operation on two input addresses and stores the result
+  /// in the output address
+  And(usize, usize, usize),
+
+  /// Performs an Or operation on two input addresses and stores the result in
+  /// the output address
+  Or(usize, usize, usize),
 }
 
 pub type Ops = Vec<Op>;
 
+impl fmt::Display for Op {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&format_op(*self, |reg| format!("r{}", reg)))
+  }
+}
+
+/// Formats an op like its `Display`, with each register given by `name`
+fn format_op(op: Op, name: impl Fn(usize) -> String) -> String {
+  match op {
+    Op::Nand(a, b, out) => {
+      format!("NAND {} {} -> {}", name(a), name(b), name(out))
+    }
+    Op::Set(reg, val) => format!("SET {} {}", name(reg), val),
+    Op::Buf(a, out) => format!("BUF {} -> {}", name(a), name(out)),
+    Op::Inv(a, out) => format!("INV {} -> {}", name(a), name(out)),
+    Op::And(a, b, out) => {
+      format!("AND {} {} -> {}", name(a), name(b), name(out))
+    }
+    Op::Or(a, b, out) => format!("OR {} {} -> {}", name(a), name(b), name(out)),
+  }
+}
+
+/// What the gates are lowered to when compiling
+#[derive(
+  Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum LoweringStrategy {
+  /// Every gate bottoms out in Nand ops
+  #[default]
+  NandOnly,
+
+  /// Ands, Ors and Nots are kept as their own ops, which are easier to read
+  /// in exported circuits
+  Primitives,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+  /// More than one op writes to the register
+  MultipleDrivers { reg: usize },
+
+  /// Gates that failed validation, with the index of each gate
+  Validation(Vec<(usize, ValidationError)>),
+
+  /// An initial value is given for a register outside of the simulation
+  InitOutOfRange { reg: usize, register_count: usize },
+}
+
+impl fmt::Display for CompileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CompileError::MultipleDrivers { reg } => {
+        write!(f, "register {} has more than one driver", reg)
+      }
+      CompileError::Validation(errors) => {
+        let errors = errors
+          .iter()
+          .map(|(index, err)| format!("gate {}: {}", index, err))
+          .collect::<Vec<_>>();
+        write!(f, "invalid gates ({})", errors.join(", "))
+      }
+      CompileError::InitOutOfRange {
+        reg,
+        register_count,
+      } => write!(
+        f,
+        "initial register {} is out of range of the {} registers",
+        reg, register_count
+      ),
+    }
+  }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleError {
+  /// An op reads a register before the op that writes to it runs, and the
+  /// register isn't fed back from the op's own output
+  ReadBeforeWrite { op: usize, reg: usize },
+}
+
+impl fmt::Display for ScheduleError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ScheduleError::ReadBeforeWrite { op, reg } => {
+        write!(f, "op {} reads register {} before it's written", op, reg)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ScheduleError {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Incrementer {
   pub val: usize,
@@ -48,7 +162,7 @@ impl Default for Incrementer {
   }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Compiler {
   /// The number of immediate values to allocate when running the simulation
   pub immediate_count: usize,
@@ -58,6 +172,42 @@ pub struct Compiler {
 
   /// Incrementer for allocating registers
   pub incrementer: Incrementer,
+
+  /// The ops scheduled in each layer by the last compile, starting with the
+  /// Sets of the immediates (which the simulation sets directly instead)
+  #[serde(default)]
+  pub layers: Vec<Ops>,
+
+  /// Ops merged from other compilers, which are included in every compile
+  #[serde(default)]
+  pub merged: Ops,
+
+  /// Registers tied to another register, which they're resolved to
+  #[serde(default)]
+  pub ties: HashMap<usize, usize>,
+
+  /// The seed the power-on values of registers are picked from, otherwise
+  /// they start low
+  #[serde(default)]
+  pub initial_state: Option<u64>,
+
+  /// The label of each gate of the last compile (see [`Gate::dot_label`])
+  /// and the registers its ops write to
+  #[serde(default)]
+  pub gate_groups: Vec<(String, Vec<usize>)>,
+
+  /// What the gates are lowered to
+  #[serde(default)]
+  pub lowering: LoweringStrategy,
+
+  /// Names of registers, such as the inputs and outputs, which the
+  /// simulations it compiles are labeled with
+  #[serde(default)]
+  pub labels: HashMap<usize, String>,
+
+  /// The number of registers of the last compile or append
+  #[serde(default)]
+  register_count: usize,
 }
 
 impl Compiler {
@@ -67,9 +217,34 @@ impl Compiler {
       ops: vec![],
       immediate_count,
       incrementer: Incrementer::set(immediate_count),
+      layers: vec![],
+      merged: vec![],
+      ties: HashMap::new(),
+      initial_state: None,
+      gate_groups: vec![],
+      lowering: LoweringStrategy::NandOnly,
+      labels: HashMap::new(),
+      register_count: 0,
     }
   }
 
+  /// Powers on the registers written by ops at random values picked from the
+  /// seed, like the feedback of a real latch
+  ///
+  /// Registers with an initial value, such as latches with a chosen
+  /// `initial`, still start with it. Circuits that are robust to their
+  /// power-on state give the same results with any seed once stabilized.
+  pub fn with_initial_state(mut self, seed: u64) -> Self {
+    self.initial_state = Some(seed);
+    self
+  }
+
+  /// Picks what the gates are lowered to when compiling
+  pub fn with_lowering(mut self, lowering: LoweringStrategy) -> Self {
+    self.lowering = lowering;
+    self
+  }
+
   /// Resets ops
   pub fn reset_ops(&mut self) {
     self.ops.clear();
@@ -89,41 +264,228 @@ impl Compiler {
     self.incrementer.next()
   }
 
+  /// Resets the incrementer past the registers of a previous compile
+  ///
+  /// The registers in `hint` are never handed out by `alloc` again, so they
+  /// can be reused with `alloc_hinted` to keep register indices stable across
+  /// recompiles.
+  pub fn reset_incrementer_hinted<K>(&mut self, hint: &HashMap<K, usize>) {
+    self.reset_incrementer();
+    if let Some(max) = hint.values().max() {
+      self.incrementer.val = self.incrementer.val.max(max + 1);
+    }
+  }
+
+  /// Allocates a register for the key, reusing its register from `hint` if it
+  /// had one
+  pub fn alloc_hinted<K: Eq + Hash>(
+    &mut self,
+    key: &K,
+    hint: &HashMap<K, usize>,
+  ) -> usize {
+    match hint.get(key) {
+      // Immediates are always allocated first, so a hinted register that is
+      // now an immediate can't be reused
+      Some(reg) if *reg >= self.immediate_count => *reg,
+      _ => self.alloc(),
+    }
+  }
+
+  /// Ties two registers into one wire, so ops using either register use the
+  /// lower register of the two instead
+  pub fn tie(&mut self, a: usize, b: usize) {
+    let [a, b] = [self.resolve(a), self.resolve(b)];
+    if a != b {
+      self.ties.insert(a.max(b), a.min(b));
+    }
+  }
+
+  /// Names a register, replacing any name it had
+  pub fn label(&mut self, reg: usize, name: &str) {
+    self.labels.insert(reg, name.to_string());
+  }
+
+  /// Gets the labels with the registers they're tied to, where the lowest
+  /// register of a tie keeps its label
+  fn resolved_labels(&self) -> HashMap<usize, String> {
+    let mut labels: Vec<(&usize, &String)> = self.labels.iter().collect();
+    labels.sort();
+
+    let mut resolved = HashMap::new();
+    for (reg, label) in labels {
+      resolved
+        .entry(self.resolve(*reg))
+        .or_insert_with(|| label.clone());
+    }
+
+    resolved
+  }
+
+  /// Gets the register that a register is tied to, or the register itself
+  pub fn resolve(&self, mut reg: usize) -> usize {
+    while let Some(tied) = self.ties.get(&reg) {
+      reg = *tied;
+    }
+
+    reg
+  }
+
+  /// Lists the ops of the last compile under a header for each layer
+  ///
+  /// The layers are scheduled after ties are resolved, so each op shows the
+  /// registers it actually reads and writes. Registers are shown by their
+  /// label (see [`Compiler::label`]) if they have one.
+  pub fn pretty_print(&self) -> String {
+    let labels = self.resolved_labels();
+    let name = |reg: usize| match labels.get(&reg) {
+      Some(label) => label.clone(),
+      None => format!("r{}", reg),
+    };
+
+    let mut s = String::new();
+    for (i, layer) in self.layers.iter().enumerate() {
+      s.push_str(&format!("layer {}:\n", i));
+      for op in layer.iter() {
+        s.push_str(&format!("  {}\n", format_op(*op, name)));
+      }
+    }
+
+    s
+  }
+
+  /// Rewrites the ops to use the registers they're tied to
+  fn resolve_ties(&self, ops: &mut Ops) {
+    if self.ties.is_empty() {
+      return;
+    }
+
+    for op in ops.iter_mut() {
+      *op = match *op {
+        Op::Nand(a, b, out) => {
+          Op::Nand(self.resolve(a), self.resolve(b), self.resolve(out))
+        }
+        Op::Set(reg, val) => Op::Set(self.resolve(reg), val),
+        Op::Buf(a, out) => Op::Buf(self.resolve(a), self.resolve(out)),
+        Op::Inv(a, out) => Op::Inv(self.resolve(a), self.resolve(out)),
+        Op::And(a, b, out) => {
+          Op::And(self.resolve(a), self.resolve(b), self.resolve(out))
+        }
+        Op::Or(a, b, out) => {
+          Op::Or(self.resolve(a), self.resolve(b), self.resolve(out))
+        }
+      };
+    }
+  }
+
   /// Compiles a list of gates into Ops
-  pub fn compile(&mut self, gates: Vec<&Gate>) -> Simulation {
+  ///
+  /// Each gate is simplified (see [`Gate::simplify`]) before it's lowered.
+  pub fn compile(
+    &mut self,
+    gates: Vec<&Gate>,
+  ) -> Result<Simulation, CompileError> {
+    let errors: Vec<(usize, ValidationError)> = gates
+      .iter()
+      .enumerate()
+      .filter_map(|(index, gate)| {
+        gate
+          .validate(self.incrementer.val)
+          .err()
+          .map(|err| (index, err))
+      })
+      .collect();
+    if !errors.is_empty() {
+      return Err(CompileError::Validation(errors));
+    }
+
     self.reset_ops();
+    self.layers.clear();
+    self.gate_groups.clear();
 
-    if gates.is_empty() {
-      return Simulation {
+    let mut visible: Vec<usize> = gates
+      .iter()
+      .flat_map(|gate| gate.outputs())
+      .map(|reg| self.resolve(reg))
+      .collect();
+    visible.sort_unstable();
+    visible.dedup();
+
+    if gates.is_empty() && self.merged.is_empty() {
+      self.register_count = self.immediate_count;
+      return Ok(Simulation {
         registers: vec![false; self.immediate_count],
-        ops: vec![],
-      };
+        immediate_count: self.immediate_count,
+        readers: vec![vec![]; self.immediate_count],
+        labels: self.resolved_labels(),
+        ..Default::default()
+      });
     }
 
     // Cloning incrementer since we are generating ops and we don't
     // want to change the incrementer for top-level gates (what we are compiling)
     let mut incrementer = self.incrementer.clone();
+    self.ops.extend(self.merged.iter().copied());
     gates.into_iter().for_each(|gate| {
-      self.ops.extend(gate.create(&mut incrementer));
+      let ops = gate.simplify().create_with(&mut incrementer, self.lowering);
+      let outputs = ops
+        .iter()
+        .filter(|op| !matches!(op, Op::Set(..)))
+        .map(|op| self.resolve(op_output(*op)))
+        .collect();
+      self.gate_groups.push((gate.dot_label(), outputs));
+      self.ops.extend(ops);
     });
+    debug_assert!(
+      self.lowering == LoweringStrategy::Primitives || is_nand_only(&self.ops),
+      "gates lowered to other ops"
+    );
+    let mut ops = std::mem::take(&mut self.ops);
+    self.resolve_ties(&mut ops);
+    self.ops = ops;
+    let initial = take_initial_values(&mut self.ops, self.immediate_count);
 
+    // Immediates are only written by their Set, so they're left out
+    let mut driven: HashSet<usize> = HashSet::new();
+    for op in self.ops.iter() {
+      if !matches!(op, Op::Set(..)) {
+        let reg = op_output(*op);
+        if !driven.insert(reg) {
+          return Err(CompileError::MultipleDrivers { reg });
+        }
+      }
+    }
+
+    // One node per register, so registers that are allocated but never
+    // written to still have a node
     let mut graph = DiGraph::<Op, (), usize>::default();
-    self.ops.iter().for_each(|op| {
-      graph.add_node(*op);
+    (0..incrementer.val).for_each(|reg| {
+      graph.add_node(Op::Set(reg, false));
     });
 
     self.ops.iter().for_each(|op| {
-      if let Op::Nand(a, b, out) = op {
-        if *a < graph.node_count()
-          && *b < graph.node_count()
-          && *out < graph.node_count()
-        {
-          graph.add_edge(NodeIndex::from(*a), NodeIndex::from(*out), ());
-          graph.add_edge(NodeIndex::from(*b), NodeIndex::from(*out), ());
+      let inputs = op_inputs(*op);
+      let out = op_output(*op);
+      if inputs
+        .iter()
+        .chain([&out])
+        .all(|reg| *reg < graph.node_count())
+      {
+        for input in inputs {
+          graph.add_edge(NodeIndex::from(input), NodeIndex::from(out), ());
         }
       }
     });
 
+    // Ops waiting to be scheduled are visited in the order the gates emitted
+    // them, so ops in the same layer keep the order of their gates
+    let emitted: HashMap<usize, usize> = self
+      .ops
+      .iter()
+      .enumerate()
+      .map(|(i, op)| (op_output(*op), i))
+      .collect();
+    let order = |node: &usize| (emitted.get(node).copied(), *node);
+
     let mut ops: Vec<Op> = vec![];
     let mut nodes_to_process: HashSet<usize> = HashSet::default();
     let mut queue: Vec<usize> = vec![];
@@ -133,10 +495,6 @@ impl Compiler {
     for op in self.ops.iter() {
       let op = *op;
       match op {
-        Op::Nand(_, _, out) => {
-          graph[NodeIndex::from(out)] = op;
-          nodes_to_process.insert(out);
-        }
         Op::Set(reg, _) => {
           graph[NodeIndex::from(reg)] = op;
 
@@ -144,15 +502,31 @@ impl Compiler {
           queue.push(reg);
           nodes_to_process.insert(reg);
         }
+        _ => {
+          let out = op_output(op);
+          graph[NodeIndex::from(out)] = op;
+          nodes_to_process.insert(out);
+        }
       };
     }
 
-    fs::write("graph.dot", format!("{:?}", Dot::with_config(&graph, &[])))
-      .expect("Unable to write file");
+    // Ops that only read registers nothing writes to, like the output of a
+    // constant, are ready from the start like the immediates
+    for op in self.ops.iter().filter(|op| !matches!(op, Op::Set(..))) {
+      if op_inputs(*op)
+        .iter()
+        .all(|reg| !nodes_to_process.contains(reg))
+      {
+        queue.push(op_output(*op));
+      }
+    }
 
-    // Flag to force-add all gates in the queue if recursion is detected
-    let mut recursion_flag = false;
+    // Ops to force-add if recursion is detected, which are the ones in a
+    // loop. Ops that only read from a loop wait for it, so they don't read
+    // stale values.
+    let mut forced: HashSet<usize> = HashSet::default();
     loop {
+      let layer_start = ops.len();
       for node in queue.iter() {
         let node = *node;
         if !nodes_to_process.contains(&node) {
@@ -162,8 +536,8 @@ impl Compiler {
         let mut inputs =
           graph.neighbors_directed(NodeIndex::from(node), Direction::Incoming);
 
-        let requires_new_layer = if recursion_flag {
-          false
+        let requires_new_layer = if !forced.is_empty() {
+          !forced.contains(&node)
         } else {
           inputs.any(|i| nodes_to_process.contains(&i.index()))
         };
@@ -182,10 +556,28 @@ impl Compiler {
         }
       }
 
-      queue.sort();
-      next_queue.sort();
+      if ops.len() > layer_start {
+        self.layers.push(ops[layer_start..].to_vec());
+      }
+
+      queue.sort_by_key(order);
+      next_queue.sort_by_key(order);
 
-      recursion_flag = queue == next_queue;
+      forced.clear();
+      if queue == next_queue {
+        forced.extend(queue.iter().copied().filter(|node| {
+          graph
+            .neighbors_directed(NodeIndex::from(*node), Direction::Incoming)
+            .any(|input| {
+              has_path_connecting(&graph, NodeIndex::from(*node), input, None)
+            })
+        }));
+
+        // Fall back to every op if none of them are in a loop
+        if forced.is_empty() {
+          forced.extend(queue.iter().copied());
+        }
+      }
 
       std::mem::swap(&mut queue, &mut next_queue);
       next_queue.clear();
@@ -195,16 +587,449 @@ impl Compiler {
       }
     }
 
-    Simulation {
-      registers: vec![false; incrementer.val],
+    let mut registers = vec![false; incrementer.val];
+    if let Some(mut seed) = self.initial_state {
+      for op in ops.iter().filter(|op| !matches!(op, Op::Set(..))) {
+        registers[op_output(*op)] = splitmix64(&mut seed) & 1 == 1;
+      }
+    }
+    for (reg, val) in initial {
+      registers[reg] = val;
+    }
+
+    // The simulation sets the immediates from the run, so their Sets are only
+    // needed for scheduling
+    let immediate_count = self.immediate_count;
+    ops.retain(|op| !matches!(op, Op::Set(reg, _) if *reg < immediate_count));
+
+    let mut simulation = Simulation {
+      registers,
       ops,
+      immediate_count: self.immediate_count,
+      visible,
+      labels: self.resolved_labels(),
+      ..Default::default()
+    };
+    simulation.op_layers = self.op_layers(&simulation.ops);
+    simulation.index_readers();
+    self.register_count = simulation.registers.len();
+
+    Ok(simulation)
+  }
+
+  /// Compiles a list of gates like [`Compiler::compile`], starting the
+  /// registers in `init` at their given values instead
+  ///
+  /// Tied registers start the register they're tied to. The immediates are
+  /// still set from the immediates of each run, and the registers inside of
+  /// gates (such as the complement of a latch) keep their initial values.
+  pub fn compile_with_init(
+    &mut self,
+    gates: Vec<&Gate>,
+    init: &HashMap<usize, bool>,
+  ) -> Result<Simulation, CompileError> {
+    let mut simulation = self.compile(gates)?;
+    let register_count = simulation.registers.len();
+    let out_of_range = |reg: &usize| self.resolve(*reg) >= register_count;
+    if let Some(reg) = init.keys().copied().find(out_of_range) {
+      return Err(CompileError::InitOutOfRange {
+        reg,
+        register_count,
+      });
+    }
+
+    for (reg, val) in init.iter() {
+      simulation.registers[self.resolve(*reg)] = *val;
+    }
+
+    Ok(simulation)
+  }
+
+  /// Gets the number of registers of the simulation from the last compile or
+  /// append, which includes the registers used inside of gates
+  ///
+  /// This is 0 before anything has been compiled.
+  pub fn last_register_count(&self) -> usize {
+    self.register_count
+  }
+
+  /// Renumbers the registers used by the ops into `0..n`, keeping the
+  /// immediates first and every other register in its order, and returns
+  /// where each register was moved to
+  ///
+  /// Registers that no op uses are dropped, such as ones allocated for gates
+  /// that were since removed. The ops, layers, merged ops and ties are all
+  /// updated, but gates and simulations built before still use the old
+  /// registers, which the returned map translates.
+  pub fn compact(&mut self) -> HashMap<usize, usize> {
+    let mut used: Vec<usize> = (0..self.immediate_count)
+      .chain(
+        self
+          .ops
+          .iter()
+          .chain(self.merged.iter())
+          .flat_map(|op| op_inputs(*op).into_iter().chain([op_output(*op)])),
+      )
+      .collect();
+    used.sort_unstable();
+    used.dedup();
+    let remap: HashMap<usize, usize> = used
+      .iter()
+      .enumerate()
+      .map(|(new, old)| (*old, new))
+      .collect();
+
+    let rename = |op: &mut Op| {
+      let reg = |reg: usize| remap[&reg];
+      *op = match *op {
+        Op::Nand(a, b, out) => Op::Nand(reg(a), reg(b), reg(out)),
+        Op::Set(id, val) => Op::Set(reg(id), val),
+        Op::Buf(a, out) => Op::Buf(reg(a), reg(out)),
+        Op::Inv(a, out) => Op::Inv(reg(a), reg(out)),
+        Op::And(a, b, out) => Op::And(reg(a), reg(b), reg(out)),
+        Op::Or(a, b, out) => Op::Or(reg(a), reg(b), reg(out)),
+      };
+    };
+    self.ops.iter_mut().for_each(rename);
+    self.merged.iter_mut().for_each(rename);
+    self.layers.iter_mut().flatten().for_each(rename);
+
+    self.ties = self
+      .ties
+      .iter()
+      .filter_map(|(from, to)| Some((*remap.get(from)?, *remap.get(to)?)))
+      .collect();
+    for (_, outputs) in self.gate_groups.iter_mut() {
+      outputs.retain(|reg| remap.contains_key(reg));
+      outputs.iter_mut().for_each(|reg| *reg = remap[reg]);
+    }
+    self.labels = std::mem::take(&mut self.labels)
+      .into_iter()
+      .filter_map(|(reg, label)| Some((*remap.get(&reg)?, label)))
+      .collect();
+
+    self.incrementer = Incrementer::set(used.len());
+    self.register_count = used.len();
+
+    remap
+  }
+
+  /// Gets the index of the layer each op was scheduled in by the last
+  /// compile or append
+  fn op_layers(&self, ops: &[Op]) -> Vec<usize> {
+    let layers: HashMap<usize, usize> = self
+      .layers
+      .iter()
+      .enumerate()
+      .flat_map(|(i, layer)| layer.iter().map(move |op| (op_output(*op), i)))
+      .collect();
+
+    ops
+      .iter()
+      .map(|op| layers.get(&op_output(*op)).copied().unwrap_or_default())
+      .collect()
+  }
+
+  /// Merges the ops of another compiler's last compile into this one
+  ///
+  /// Registers of the other compiler are moved to the registers they're
+  /// mapped to in `remap`, and any unmapped register is allocated a new
+  /// register. Sets on registers mapped to this compiler's immediates are
+  /// dropped, since this compiler sets them instead.
+  pub fn merge(&mut self, other: &Compiler, remap: &HashMap<usize, usize>) {
+    let mut remap = remap.clone();
+    let mut register = |reg: usize, compiler: &mut Compiler| {
+      *remap.entry(reg).or_insert_with(|| compiler.alloc())
+    };
+
+    for op in other.ops.iter() {
+      let op = match *op {
+        Op::Nand(a, b, out) => {
+          Op::Nand(register(a, self), register(b, self), register(out, self))
+        }
+        Op::Set(reg, val) => {
+          let reg = register(reg, self);
+          if reg < self.immediate_count {
+            continue;
+          }
+
+          Op::Set(reg, val)
+        }
+        Op::Buf(a, out) => Op::Buf(register(a, self), register(out, self)),
+        Op::Inv(a, out) => Op::Inv(register(a, self), register(out, self)),
+        Op::And(a, b, out) => {
+          Op::And(register(a, self), register(b, self), register(out, self))
+        }
+        Op::Or(a, b, out) => {
+          Op::Or(register(a, self), register(b, self), register(out, self))
+        }
+      };
+
+      self.merged.push(op);
+    }
+  }
+
+  /// Compiles a single gate into an already compiled simulation
+  ///
+  /// Only the new ops and the existing ops that depend on them are scheduled
+  /// again, everything else keeps its place. The gate is validated and
+  /// checked for driving a register that's already driven like it would be
+  /// by [`Compiler::compile`], leaving the simulation as it was if it fails.
+  pub fn append(
+    &mut self,
+    gate: &Gate,
+    sim: &mut Simulation,
+  ) -> Result<(), CompileError> {
+    let register_count = sim.registers.len().max(self.incrementer.val);
+    if let Err(err) = gate.validate(register_count) {
+      return Err(CompileError::Validation(vec![(0, err)]));
+    }
+
+    let mut incrementer = Incrementer::set(register_count);
+    let mut new_ops =
+      gate.simplify().create_with(&mut incrementer, self.lowering);
+    self.resolve_ties(&mut new_ops);
+    let initial = take_initial_values(&mut new_ops, self.immediate_count);
+
+    // Immediates are only written by their Set, so they're left out
+    let mut driven: HashSet<usize> = self
+      .ops
+      .iter()
+      .filter(|op| !matches!(op, Op::Set(..)))
+      .map(|op| op_output(*op))
+      .collect();
+    let outputs: Vec<usize> = new_ops
+      .iter()
+      .filter(|op| !matches!(op, Op::Set(..)))
+      .map(|op| op_output(*op))
+      .collect();
+    if let Some(reg) = outputs.iter().copied().find(|reg| !driven.insert(*reg))
+    {
+      return Err(CompileError::MultipleDrivers { reg });
+    }
+    self.gate_groups.push((gate.dot_label(), outputs));
+
+    let max_register = new_ops
+      .iter()
+      .flat_map(|op| op_inputs(*op).into_iter().chain([op_output(*op)]))
+      .max()
+      .unwrap_or(0);
+    let len = incrementer.val.max(max_register + 1);
+    if sim.registers.len() < len {
+      sim.registers.resize(len, false);
+    }
+    self.register_count = sim.registers.len();
+    for (reg, val) in initial {
+      sim.registers[reg] = val;
+    }
+
+    // Find the previously compiled ops that read from the new ops. Ops with
+    // inputs that weren't written to are left out of the simulation, so
+    // they're found in the compiler's ops instead.
+    let mut affected: HashSet<usize> =
+      new_ops.iter().map(|op| op_output(*op)).collect();
+    loop {
+      let count = affected.len();
+      for op in self.ops.iter() {
+        if op_inputs(*op).iter().any(|input| affected.contains(input)) {
+          affected.insert(op_output(*op));
+        }
+      }
+
+      if affected.len() == count {
+        break;
+      }
+    }
+
+    let mut pending: Ops = new_ops.clone();
+    pending.extend(
+      self
+        .ops
+        .iter()
+        .filter(|op| affected.contains(&op_output(**op))),
+    );
+    self.ops.extend(new_ops);
+
+    sim.ops.retain(|op| !affected.contains(&op_output(*op)));
+    self
+      .layers
+      .iter_mut()
+      .for_each(|layer| layer.retain(|op| !affected.contains(&op_output(*op))));
+    self.layers.retain(|layer| !layer.is_empty());
+
+    // Schedule an op once none of its inputs are waiting on another pending
+    // op, or schedule everything left if they're all waiting on each other
+    while !pending.is_empty() {
+      let outputs: HashSet<usize> =
+        pending.iter().map(|op| op_output(*op)).collect();
+      let (mut layer, mut waiting): (Ops, Ops) =
+        pending.iter().partition(|op| {
+          let out = op_output(**op);
+          op_inputs(**op)
+            .iter()
+            .all(|input| *input == out || !outputs.contains(input))
+        });
+
+      if layer.is_empty() {
+        std::mem::swap(&mut layer, &mut waiting);
+      }
+
+      sim.ops.extend(layer.iter().copied());
+      self.layers.push(layer);
+      pending = waiting;
+    }
+
+    for reg in gate.outputs() {
+      let reg = self.resolve(reg);
+      if let Err(i) = sim.visible.binary_search(&reg) {
+        sim.visible.insert(i, reg);
+      }
+    }
+    sim.op_layers = self.op_layers(&sim.ops);
+    sim.labels = self.resolved_labels();
+    sim.index_readers();
+
+    Ok(())
+  }
+}
+
+/// Removes the Sets that gates use for initial values from the ops
+///
+/// Only the immediates are set on every run, Sets on any other register are
+/// applied once when the simulation is created.
+fn take_initial_values(
+  ops: &mut Ops,
+  immediate_count: usize,
+) -> Vec<(usize, bool)> {
+  let mut initial = vec![];
+  ops.retain(|op| match *op {
+    Op::Set(reg, val) if reg >= immediate_count => {
+      initial.push((reg, val));
+      false
+    }
+    _ => true,
+  });
+
+  initial
+}
+
+/// Steps a SplitMix64 generator, returning its next value
+fn splitmix64(state: &mut u64) -> u64 {
+  *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  let mut z = *state;
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z ^ (z >> 31)
+}
+
+/// Gets the register an op writes to
+pub fn op_output(op: Op) -> usize {
+  match op {
+    Op::Nand(_, _, out)
+    | Op::Buf(_, out)
+    | Op::Inv(_, out)
+    | Op::And(_, _, out)
+    | Op::Or(_, _, out) => out,
+    Op::Set(reg, _) => reg,
+  }
+}
+
+/// Orders the inputs of an op whose inputs can be swapped (Nand, And and Or)
+/// so the lower register comes first, so ops that only differ by the order
+/// of their inputs compare equal
+pub fn normalize_op(op: Op) -> Op {
+  match op {
+    Op::Nand(a, b, out) if a > b => Op::Nand(b, a, out),
+    Op::And(a, b, out) if a > b => Op::And(b, a, out),
+    Op::Or(a, b, out) if a > b => Op::Or(b, a, out),
+    op => op,
+  }
+}
+
+/// Checks that the ops are only Nands and Sets, which is what every gate
+/// lowers to by default
+///
+/// Buf ops only come from folding a compiled simulation, and Inv, And and Or
+/// ops from folding or [`LoweringStrategy::Primitives`], so otherwise they
+/// mean something other than a gate made it into the ops.
+pub fn is_nand_only(ops: &[Op]) -> bool {
+  ops
+    .iter()
+    .all(|op| matches!(op, Op::Nand(..) | Op::Set(..)))
+}
+
+/// Checks that every op runs after the ops that write to the registers it
+/// reads
+///
+/// Registers that no op writes to, such as immediates that aren't set, are
+/// always ready. A register can be read before it's written when it's fed
+/// back from the reading op's output, like the loop of a latch.
+pub fn verify_schedule(ops: &[Op]) -> Result<(), ScheduleError> {
+  let driven: HashSet<usize> = ops.iter().map(|op| op_output(*op)).collect();
+
+  // The index of every op that reads from each register
+  let mut readers: HashMap<usize, Vec<usize>> = HashMap::new();
+  for (i, op) in ops.iter().enumerate() {
+    for input in op_inputs(*op) {
+      readers.entry(input).or_default().push(i);
+    }
+  }
+
+  // Whether the output of op `i` reaches `reg` through the ops after it
+  let feeds_back = |i: usize, reg: usize| {
+    let mut seen: HashSet<usize> = HashSet::from([i]);
+    let mut stack = vec![i];
+    while let Some(i) = stack.pop() {
+      let out = op_output(ops[i]);
+      if out == reg {
+        return true;
+      }
+
+      for reader in readers.get(&out).into_iter().flatten() {
+        if seen.insert(*reader) {
+          stack.push(*reader);
+        }
+      }
+    }
+
+    false
+  };
+
+  let mut written: HashSet<usize> = HashSet::new();
+  for (i, op) in ops.iter().enumerate() {
+    for reg in op_inputs(*op) {
+      if driven.contains(&reg) && !written.contains(&reg) && !feeds_back(i, reg)
+      {
+        return Err(ScheduleError::ReadBeforeWrite { op: i, reg });
+      }
+    }
+
+    written.insert(op_output(*op));
+  }
+
+  Ok(())
+}
+
+/// Gets the registers an op reads from
+pub fn op_inputs(op: Op) -> Vec<usize> {
+  match op {
+    Op::Nand(a, b, _) | Op::And(a, b, _) | Op::Or(a, b, _) if a == b => {
+      vec![a]
     }
+    Op::Nand(a, b, _) | Op::And(a, b, _) | Op::Or(a, b, _) => vec![a, b],
+    Op::Buf(a, _) | Op::Inv(a, _) => vec![a],
+    Op::Set(..) => vec![],
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use crate::{And, RSLatchTest};
+  use std::collections::HashSet;
+
+  use crate::{
+    to_bits, And, FullAdder, HalfAdder, Nand, Nor, Not, Or, RSLatch,
+    RSLatchTest, Xor,
+  };
 
   use super::*;
 
@@ -232,7 +1057,7 @@ mod tests {
     let mut compiler = Compiler::new(2);
 
     // Two immediates = two registers allocated
-    let simulation = compiler.compile(vec![]);
+    let simulation = compiler.compile(vec![]).unwrap();
     assert_eq!(simulation.registers.len(), 2);
 
     // Two immediates = the next index should be 2, then 3
@@ -240,7 +1065,7 @@ mod tests {
     assert_eq!(compiler.alloc(), 3);
 
     // Two immediates and it ignores the registers we allocated
-    let simulation = compiler.compile(vec![]);
+    let simulation = compiler.compile(vec![]).unwrap();
     assert_eq!(simulation.registers.len(), 2);
   }
 
@@ -257,14 +1082,145 @@ mod tests {
     };
 
     // When compiling, we should not increment the incrementer
-    compiler.compile(vec![&Gate::from(and)]);
-    compiler.compile(vec![&Gate::from(and)]);
-    let simulation = compiler.compile(vec![&Gate::from(and)]);
+    compiler.compile(vec![&Gate::from(and)]).unwrap();
+    compiler.compile(vec![&Gate::from(and)]).unwrap();
+    let simulation = compiler.compile(vec![&Gate::from(and)]).unwrap();
 
     // Two immediates plus two outputs for the And's internal Nand gates
     assert_eq!(simulation.registers.len(), 4);
   }
 
+  #[test]
+  /// Test that compacting closes the gap left by an unused register, and the
+  /// compacted ops run the same
+  fn compact() {
+    let mut compiler = Compiler::new(2);
+    let not = Not {
+      a: 0,
+      out: compiler.alloc(),
+    };
+    let freed = compiler.alloc();
+    let and = And {
+      a: not.out,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    compiler.label(and.out, "out");
+    compiler.label(freed, "freed");
+    compiler
+      .compile(vec![&Gate::from(not), &Gate::from(and)])
+      .unwrap();
+
+    let remap = compiler.compact();
+    assert!(!remap.contains_key(&freed));
+    assert_eq!(compiler.labels, HashMap::from([(freed, "out".to_string())]));
+    assert_eq!(remap[&0], 0);
+    assert_eq!(remap[&1], 1);
+    assert_eq!(remap[&not.out], not.out);
+    assert_eq!(remap[&and.out], freed);
+
+    let mut used: Vec<usize> = compiler
+      .ops
+      .iter()
+      .flat_map(|op| op_inputs(*op).into_iter().chain([op_output(*op)]))
+      .collect();
+    used.sort_unstable();
+    used.dedup();
+    let count = compiler.last_register_count();
+    assert_eq!(used, (0..count).collect::<Vec<_>>());
+    assert_eq!(compiler.alloc(), count);
+
+    let ops = compiler.layers.concat();
+    let mut simulation = Simulation::from_ops(ops, count).unwrap();
+    for (a, b) in [(false, false), (false, true), (true, true)] {
+      simulation.run(&[a, b]);
+      assert_eq!(simulation.registers[remap[&and.out]], !a && b);
+    }
+  }
+
+  #[test]
+  /// Test that the compiler reports the register count of its last compile
+  fn last_register_count() {
+    let mut compiler = Compiler::new(2);
+    assert_eq!(compiler.last_register_count(), 0);
+
+    let and = And {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let simulation = compiler.compile(vec![&Gate::from(and)]).unwrap();
+
+    // Two immediates plus two outputs for the And's internal Nand gates
+    assert_eq!(compiler.last_register_count(), 4);
+    assert_eq!(compiler.last_register_count(), simulation.registers.len());
+  }
+
+  #[test]
+  /// Test that a preloaded register that no gate writes to, like ROM, starts
+  /// with its value and keeps it
+  fn compile_with_init() {
+    let mut compiler = Compiler::new(1);
+    let rom = compiler.alloc();
+    let and = And {
+      a: 0,
+      b: rom,
+      out: compiler.alloc(),
+    };
+    let mut simulation = compiler
+      .compile_with_init(vec![&Gate::from(and)], &HashMap::from([(rom, true)]))
+      .unwrap();
+    assert!(simulation.register(rom));
+    assert!(!simulation.register(and.out));
+
+    simulation.run(&[true]);
+    assert!(simulation.register(rom));
+    assert!(simulation.register(and.out));
+
+    assert_eq!(
+      compiler
+        .compile_with_init(vec![&Gate::from(and)], &HashMap::from([(9, true)]))
+        .err(),
+      Some(CompileError::InitOutOfRange {
+        reg: 9,
+        register_count: 4
+      })
+    );
+  }
+
+  #[test]
+  /// Test that recompiling with a hint keeps the registers of existing gates
+  fn recompile_keeps_hinted_registers() {
+    let mut compiler = Compiler::new(2);
+    let hint = HashMap::new();
+
+    compiler.reset_incrementer_hinted(&hint);
+    let and_out = compiler.alloc_hinted(&"and", &hint);
+    let and = Gate::from(And {
+      a: 0,
+      b: 1,
+      out: and_out,
+    });
+    compiler.compile(vec![&and]).unwrap();
+
+    // Allocate the new Not gate first, which would take the And's register
+    // without the hint
+    let hint = HashMap::from([("and", and_out)]);
+    compiler.reset_incrementer_hinted(&hint);
+    let not_out = compiler.alloc_hinted(&"not", &hint);
+    assert_eq!(compiler.alloc_hinted(&"and", &hint), and_out);
+    assert_ne!(not_out, and_out);
+
+    let not = Gate::from(Not {
+      a: and_out,
+      out: not_out,
+    });
+    let mut simulation = compiler.compile(vec![&and, &not]).unwrap();
+    simulation.run(&[true, true]);
+    assert!(simulation.registers[and_out]);
+    assert!(!simulation.registers[not_out]);
+  }
+
   #[test]
   /// Custom compiling a RS Latch that has self-referencing gates
   fn non_sorted_gates_should_sort() {
@@ -277,7 +1233,7 @@ mod tests {
       q: compiler.alloc(),
     };
 
-    let mut simulation = compiler.compile(vec![&Gate::from(rslatch)]);
+    let mut simulation = compiler.compile(vec![&Gate::from(rslatch)]).unwrap();
 
     // Reset the latch (due to the nature of logic, it starts as set when it's created)
     simulation.run(&[false, false]);
@@ -292,4 +1248,503 @@ mod tests {
     simulation.run(&[true, true]);
     assert!(!simulation.registers[rslatch.q]);
   }
+
+  #[test]
+  /// Test that appending a gate matches compiling both gates from scratch
+  fn append_matches_compile() {
+    let mut compiler = Compiler::new(2);
+    let [a, b] = [0, 1];
+
+    let or = Gate::from(Or {
+      a,
+      b,
+      out: compiler.alloc(),
+    });
+    let and = And {
+      a,
+      b,
+      out: compiler.alloc(),
+    };
+
+    let mut appended = compiler.compile(vec![&or]).unwrap();
+    compiler.append(&Gate::from(and), &mut appended).unwrap();
+    assert_eq!(compiler.gate_groups.len(), 2);
+
+    let mut compiled = compiler.compile(vec![&or, &Gate::from(and)]).unwrap();
+
+    for inputs in [[false, false], [false, true], [true, false], [true, true]] {
+      appended.run(&inputs);
+      compiled.run(&inputs);
+      assert_eq!(appended.registers[2..4], compiled.registers[2..4]);
+    }
+  }
+
+  #[test]
+  /// Test that existing ops reading from an appended gate are moved after it
+  fn append_reschedules_dependents() {
+    let mut compiler = Compiler::new(2);
+    let [a, b] = [0, 1];
+
+    let and = And {
+      a,
+      b,
+      out: compiler.alloc(),
+    };
+    let not = Not {
+      a: and.out,
+      out: compiler.alloc(),
+    };
+
+    let mut simulation = compiler.compile(vec![&Gate::from(not)]).unwrap();
+    compiler.append(&Gate::from(and), &mut simulation).unwrap();
+
+    simulation.run(&[true, true]);
+    assert!(!simulation.registers[not.out]);
+
+    simulation.run(&[true, false]);
+    assert!(simulation.registers[not.out]);
+  }
+
+  #[test]
+  /// Test that appending a gate is checked like compiling it, without
+  /// changing the simulation when it fails
+  fn append_errors() {
+    let mut compiler = Compiler::new(2);
+    let not = Not {
+      a: 0,
+      out: compiler.alloc(),
+    };
+    let mut simulation = compiler.compile(vec![&Gate::from(not)]).unwrap();
+    let ops = simulation.ops.clone();
+
+    assert_eq!(
+      compiler.append(&Gate::from(Not { a: 1, out: not.out }), &mut simulation),
+      Err(CompileError::MultipleDrivers { reg: not.out })
+    );
+    assert!(matches!(
+      compiler.append(&Gate::from(Not { a: 1, out: 9 }), &mut simulation),
+      Err(CompileError::Validation(_))
+    ));
+    assert_eq!(simulation.ops, ops);
+    assert_eq!(compiler.gate_groups.len(), 1);
+  }
+
+  #[test]
+  /// Test that two merged And compilers both work in the combined simulation
+  fn merge_two_ands() {
+    let mut and_compiler = Compiler::new(2);
+    let and = And {
+      a: 0,
+      b: 1,
+      out: and_compiler.alloc(),
+    };
+    and_compiler.compile(vec![&Gate::from(and)]).unwrap();
+
+    let mut compiler = Compiler::new(4);
+    let [out_1, out_2] = [compiler.alloc(), compiler.alloc()];
+
+    compiler.merge(
+      &and_compiler,
+      &HashMap::from([(0, 0), (1, 1), (and.out, out_1)]),
+    );
+    compiler.merge(
+      &and_compiler,
+      &HashMap::from([(0, 2), (1, 3), (and.out, out_2)]),
+    );
+
+    let mut simulation = compiler.compile(vec![]).unwrap();
+    for inputs in 0..16 {
+      let inputs = to_bits(inputs, 4);
+      simulation.run(&inputs);
+
+      assert_eq!(simulation.registers[out_1], inputs[0] && inputs[1]);
+      assert_eq!(simulation.registers[out_2], inputs[2] && inputs[3]);
+    }
+  }
+
+  #[test]
+  /// Test that a gate reading a register tied to an immediate reads the
+  /// immediate
+  fn tie_immediate_to_input() {
+    let mut compiler = Compiler::new(1);
+
+    let wire = compiler.alloc();
+    let not = Not {
+      a: wire,
+      out: compiler.alloc(),
+    };
+    compiler.tie(0, wire);
+    assert_eq!(compiler.resolve(wire), 0);
+
+    let mut simulation = compiler.compile(vec![&Gate::from(not)]).unwrap();
+
+    simulation.run(&[true]);
+    assert!(!simulation.registers[not.out]);
+
+    simulation.run(&[false]);
+    assert!(simulation.registers[not.out]);
+  }
+
+  #[test]
+  /// Test that tying the outputs of two gates together is an error
+  fn tie_two_outputs() {
+    let mut compiler = Compiler::new(2);
+
+    let not_a = Not {
+      a: 0,
+      out: compiler.alloc(),
+    };
+    let not_b = Not {
+      a: 1,
+      out: compiler.alloc(),
+    };
+    compiler.tie(not_a.out, not_b.out);
+
+    assert_eq!(
+      compiler
+        .compile(vec![&Gate::from(not_a), &Gate::from(not_b)])
+        .err(),
+      Some(CompileError::MultipleDrivers { reg: not_a.out })
+    );
+  }
+
+  #[test]
+  /// Test that two gates writing to the same register is an error
+  fn multiple_drivers() {
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+
+    let and = And { a: 0, b: 1, out };
+    let or = Or { a: 0, b: 1, out };
+
+    assert_eq!(
+      compiler
+        .compile(vec![&Gate::from(and), &Gate::from(or)])
+        .err(),
+      Some(CompileError::MultipleDrivers { reg: out })
+    );
+  }
+
+  #[test]
+  /// Test that gates using registers that weren't allocated are rejected
+  fn validate_on_compile() {
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+
+    let and = And { a: 0, b: 1, out };
+    let not = Not { a: out, out: 7 };
+
+    assert_eq!(
+      compiler
+        .compile(vec![&Gate::from(and), &Gate::from(not)])
+        .err(),
+      Some(CompileError::Validation(vec![(
+        1,
+        ValidationError::RegisterOutOfRange {
+          reg: 7,
+          register_count: 3
+        }
+      )]))
+    );
+  }
+
+  #[test]
+  /// Test that a latch settles the same way from any power-on state
+  fn initial_state_seeds() {
+    let mut power_on: HashSet<Vec<bool>> = HashSet::new();
+
+    for seed in 0..32 {
+      let mut compiler = Compiler::new(2).with_initial_state(seed);
+      let latch = RSLatch {
+        s: 0,
+        r: 1,
+        q: compiler.alloc(),
+        initial: None,
+      };
+      let chosen = RSLatch {
+        q: compiler.alloc(),
+        initial: Some(false),
+        ..latch
+      };
+      let mut simulation = compiler
+        .compile(vec![&Gate::from(latch), &Gate::from(chosen)])
+        .unwrap();
+      power_on.insert(simulation.registers.clone());
+
+      // A chosen initial value isn't randomized
+      assert!(!simulation.registers[chosen.q]);
+
+      // Reset, hold, set, and hold again
+      let steps = [
+        (false, true, false),
+        (false, false, false),
+        (true, false, true),
+        (false, false, true),
+      ];
+      for (s, r, q) in steps {
+        assert!(simulation.run_until_stable(&[s, r], 100), "seed {}", seed);
+        assert_eq!(simulation.registers[latch.q], q, "seed {}", seed);
+        assert_eq!(simulation.registers[chosen.q], q, "seed {}", seed);
+      }
+    }
+
+    assert!(power_on.len() > 1);
+  }
+
+  #[test]
+  /// Test that pretty printing lists every op under its layer
+  fn pretty_print_half_adder() {
+    let mut compiler = Compiler::new(2);
+    let s = compiler.alloc();
+    let c = compiler.alloc();
+    compiler
+      .compile(vec![&Gate::from(HalfAdder { a: 0, b: 1, s, c })])
+      .unwrap();
+
+    let printed = compiler.pretty_print();
+    let headers = printed.lines().filter(|line| line.starts_with("layer"));
+    let ops = printed.lines().filter(|line| line.starts_with("  "));
+    assert_eq!(headers.count(), 4);
+    assert_eq!(ops.count(), 10);
+    assert!(printed.starts_with("layer 0:\n  SET r0 false\n"));
+    assert!(printed.contains(&format!("-> r{}\n", s)));
+
+    // Labeled registers are shown by their labels
+    compiler.label(0, "a");
+    compiler.label(s, "sum");
+    let printed = compiler.pretty_print();
+    assert!(printed.starts_with("layer 0:\n  SET a false\n"));
+    assert!(printed.contains("-> sum\n"));
+    assert!(!printed.contains(&format!("r{}", s)));
+  }
+
+  #[test]
+  /// Test that verifying a schedule catches an op that runs before its input
+  /// is written, but not a loop
+  fn verify_misordered_schedule() {
+    let misordered = [
+      Op::Set(0, false),
+      Op::Set(1, false),
+      Op::Nand(2, 2, 3),
+      Op::Nand(0, 1, 2),
+    ];
+    assert_eq!(
+      verify_schedule(&misordered),
+      Err(ScheduleError::ReadBeforeWrite { op: 2, reg: 2 })
+    );
+
+    let ordered = [misordered[0], misordered[1], misordered[3], misordered[2]];
+    assert_eq!(verify_schedule(&ordered), Ok(()));
+
+    // Register 3 is fed back from the output of the op reading it
+    let looped = [Op::Set(0, false), Op::Nand(0, 3, 2), Op::Nand(2, 2, 3)];
+    assert_eq!(verify_schedule(&looped), Ok(()));
+  }
+
+  #[test]
+  /// Test that an op reading from a latch is scheduled after the latch's
+  /// loop, so it sees the latch's new value in the same run
+  fn schedule_reader_after_loop() {
+    let mut compiler = Compiler::new(2);
+    let [q, out] = [compiler.alloc(), compiler.alloc()];
+    let gates = [
+      Gate::from(RSLatch {
+        s: 0,
+        r: 1,
+        q,
+        initial: None,
+      }),
+      Gate::from(Not { a: q, out }),
+    ];
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+    verify_schedule(&simulation.ops).unwrap();
+
+    for inputs in [[false, true], [false, false], [true, false], [false, false]]
+    {
+      simulation.run(&inputs);
+      assert_eq!(
+        simulation.registers[out], !simulation.registers[q],
+        "{:?}",
+        inputs
+      );
+    }
+  }
+
+  #[test]
+  /// Test that an op reading from a loop that never settles waits for the
+  /// loop, instead of being forced into the same layer as it
+  fn schedule_ring_oscillator() {
+    let mut compiler = Compiler::new(1);
+    let [a, b, c, out] = [
+      compiler.alloc(),
+      compiler.alloc(),
+      compiler.alloc(),
+      compiler.alloc(),
+    ];
+    // A ring of three inverters enabled by the immediate. The And comes
+    // first so it's emitted before the loop it reads from.
+    let gates = [
+      Gate::from(And { a: 0, b: c, out }),
+      Gate::from(Nand { a: 0, b: c, out: a }),
+      Gate::from(Not { a, out: b }),
+      Gate::from(Not { a: b, out: c }),
+    ];
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+    verify_schedule(&simulation.ops).unwrap();
+
+    let mut previous = simulation.registers[c];
+    for _ in 0..4 {
+      simulation.run(&[true]);
+      assert_ne!(simulation.registers[c], previous);
+      assert_eq!(simulation.registers[out], simulation.registers[c]);
+      previous = simulation.registers[c];
+    }
+  }
+
+  #[test]
+  /// Test that gates are simplified before they're lowered, and that ops
+  /// reading a constant are still scheduled
+  fn compile_simplifies() {
+    let mut compiler = Compiler::new(2);
+    let xor = Xor {
+      a: 0,
+      b: 0,
+      out: compiler.alloc(),
+    };
+    let nor = Nor {
+      a: xor.out,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let or = Or {
+      a: 1,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let gates = [Gate::from(xor), Gate::from(nor), Gate::from(or)];
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+
+    // The Xor is a constant and the Or is the And it simplifies to
+    let mut expected = Compiler::new(2);
+    expected.incrementer.skip(3);
+    let simplified = [
+      Gate::from(nor),
+      Gate::from(And {
+        a: 1,
+        b: 1,
+        out: or.out,
+      }),
+    ];
+    let expected = expected.compile(simplified.iter().collect()).unwrap();
+    assert_eq!(simulation.op_count(), expected.op_count());
+
+    for (a, b) in [(false, false), (true, false), (true, true)] {
+      simulation.run(&[a, b]);
+      assert!(!simulation.registers[xor.out]);
+      assert_eq!(simulation.registers[nor.out], !b);
+      assert_eq!(simulation.registers[or.out], b);
+    }
+  }
+
+  #[test]
+  /// Test that keeping primitives takes fewer ops and runs the same as
+  /// lowering to Nands
+  fn lowering_strategies() {
+    let gates = [
+      Gate::from(FullAdder {
+        a: 0,
+        b: 1,
+        cin: 2,
+        s: 3,
+        cout: 4,
+      }),
+      Gate::from(Nor { a: 0, b: 1, out: 5 }),
+    ];
+
+    let mut simulations =
+      [LoweringStrategy::NandOnly, LoweringStrategy::Primitives].map(
+        |lowering| {
+          let mut compiler = Compiler::new(3).with_lowering(lowering);
+          compiler.incrementer.skip(3);
+          compiler.compile(gates.iter().collect()).unwrap()
+        },
+      );
+
+    let [nand_only, primitives] = &simulations;
+    assert!(is_nand_only(&nand_only.ops));
+    assert!(!is_nand_only(&primitives.ops));
+    assert!(primitives.op_count() < nand_only.op_count());
+    assert!(primitives
+      .ops
+      .iter()
+      .any(|op| matches!(op, Op::And(..) | Op::Or(..) | Op::Inv(..))));
+
+    for value in 0..8 {
+      let inputs = to_bits(value, 3);
+      let outputs = simulations.each_mut().map(|simulation| {
+        simulation.run(&inputs);
+        simulation.registers[3..6].to_vec()
+      });
+      assert_eq!(outputs[0], outputs[1], "{:?}", inputs);
+    }
+  }
+
+  #[test]
+  /// Test that the immediates are set without any Set ops in the simulation
+  fn immediates_without_sets() {
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+    let mut simulation = compiler
+      .compile(vec![&Gate::from(And { a: 0, b: 1, out })])
+      .unwrap();
+
+    assert!(!simulation.ops.iter().any(|op| matches!(op, Op::Set(..))));
+    assert!(compiler.layers[0].contains(&Op::Set(0, false)));
+
+    simulation.run(&[true, true]);
+    assert_eq!(simulation.registers[..2], [true, true]);
+    assert!(simulation.registers[out]);
+
+    // Missing immediates are low
+    simulation.run(&[true]);
+    assert_eq!(simulation.registers[..2], [true, false]);
+    assert!(!simulation.registers[out]);
+
+    let run = simulation.compile_fn();
+    let mut registers = vec![false; simulation.registers.len()];
+    run(&mut registers, &[true, true]);
+    assert!(registers[out]);
+  }
+
+  #[test]
+  /// Test that ops in the same layer keep the order of their gates, and that
+  /// compiling again gives the same ops
+  fn deterministic_layer_order() {
+    let and = Gate::from(And { a: 0, b: 1, out: 4 });
+    let xor = Gate::from(Xor { a: 2, b: 3, out: 5 });
+
+    for gates in [vec![&and, &xor], vec![&xor, &and]] {
+      let mut compiler = Compiler::new(4);
+      compiler.incrementer.skip(2);
+      let simulation = compiler.compile(gates.clone()).unwrap();
+      assert_eq!(compiler.compile(gates.clone()).unwrap().ops, simulation.ops);
+
+      // The gate each op belongs to
+      let gate_of = |op: &Op| {
+        compiler
+          .gate_groups
+          .iter()
+          .position(|(_, outputs)| outputs.contains(&op_output(*op)))
+          .unwrap()
+      };
+      for (i, op) in simulation.ops.iter().enumerate() {
+        let layer = simulation.layer_of(i).unwrap();
+        assert_eq!(compiler.layers[layer].iter().find(|o| *o == op), Some(op));
+        if i > 0 && simulation.layer_of(i - 1) == Some(layer) {
+          assert!(gate_of(&simulation.ops[i - 1]) <= gate_of(op));
+        }
+      }
+      assert!(simulation.op_layers.windows(2).all(|w| w[0] <= w[1]));
+    }
+  }
 }
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+
+use crate::{op_output, Compiler, Op};
+
+/// The ops that changed between the last compiles of two compilers
+///
+/// Ops are matched by the register they write to, so an op that's rewired
+/// is changed rather than removed and added. Each list is ordered by that
+/// register.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CircuitDiff {
+  /// Ops writing to a register the old circuit didn't write to
+  pub added: Vec<Op>,
+
+  /// Ops writing to a register the new circuit doesn't write to
+  pub removed: Vec<Op>,
+
+  /// The old and new op of each register written differently
+  pub changed: Vec<(Op, Op)>,
+}
+
+impl CircuitDiff {
+  /// Checks that the circuits have the same ops
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+  }
+}
+
+/// Compares the ops of the last compile of two compilers
+pub fn diff_compilers(old: &Compiler, new: &Compiler) -> CircuitDiff {
+  let by_output = |compiler: &Compiler| -> BTreeMap<usize, Op> {
+    compiler
+      .ops
+      .iter()
+      .map(|op| (op_output(*op), *op))
+      .collect()
+  };
+  let old = by_output(old);
+  let new = by_output(new);
+
+  let mut diff = CircuitDiff::default();
+  for (reg, op) in old.iter() {
+    match new.get(reg) {
+      Some(new_op) if new_op != op => diff.changed.push((*op, *new_op)),
+      Some(_) => {}
+      None => diff.removed.push(*op),
+    }
+  }
+  diff.added = new
+    .iter()
+    .filter(|(reg, _)| !old.contains_key(reg))
+    .map(|(_, op)| *op)
+    .collect();
+
+  diff
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{And, Gate, Not};
+
+  #[test]
+  /// Test that adding a gate and rewiring a gate are told apart
+  fn diff_edits() {
+    let mut compiler = Compiler::new(3);
+    let and = And {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let not = Not {
+      a: and.out,
+      out: compiler.alloc(),
+    };
+
+    compiler.compile(vec![&Gate::from(and)]).unwrap();
+    let old = compiler.clone();
+    assert!(diff_compilers(&old, &compiler).is_empty());
+
+    // A Not lowers to a single Nand
+    compiler
+      .compile(vec![&Gate::from(and), &Gate::from(not)])
+      .unwrap();
+    assert_eq!(
+      diff_compilers(&old, &compiler),
+      CircuitDiff {
+        added: vec![Op::Nand(and.out, and.out, not.out)],
+        ..Default::default()
+      }
+    );
+
+    // Moving the And's second input only changes its Nand
+    let rewired = And { b: 2, ..and };
+    compiler.compile(vec![&Gate::from(rewired)]).unwrap();
+    let diff = diff_compilers(&old, &compiler);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    assert_eq!(diff.changed.len(), 1);
+    let (before, after) = diff.changed[0];
+    assert!(matches!(before, Op::Nand(0, 1, _)));
+    assert!(matches!(after, Op::Nand(0, 2, _)));
+
+    // Going back to the And alone removes the Not
+    let with_not = {
+      let mut compiler = compiler.clone();
+      compiler
+        .compile(vec![&Gate::from(and), &Gate::from(not)])
+        .unwrap();
+      compiler
+    };
+    assert_eq!(
+      diff_compilers(&with_not, &old).removed,
+      vec![Op::Nand(and.out, and.out, not.out)]
+    );
+  }
+}
@@ -0,0 +1,580 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{op_inputs, op_output, Compiler, Op, Simulation};
+
+/// Exports a simulation as a Berkeley Logic Interchange Format (BLIF) model
+///
+/// Registers listed in `inputs` and `outputs` use their given names, every
+/// other register is named `r{index}`. Registers that no op drives (such as
+/// the output of a constant) keep their initial value, so they're exported
+/// as constants.
+pub fn to_blif(
+  sim: &Simulation,
+  model: &str,
+  inputs: &[(usize, String)],
+  outputs: &[(usize, String)],
+) -> String {
+  let names: HashMap<usize, &str> = inputs
+    .iter()
+    .chain(outputs.iter())
+    .map(|(reg, name)| (*reg, name.as_str()))
+    .collect();
+  let name = |reg: usize| match names.get(&reg) {
+    Some(name) => name.to_string(),
+    None => format!("r{}", reg),
+  };
+
+  let mut blif = String::new();
+  blif.push_str(&format!(".model {}\n", model));
+  blif.push_str(&format!(
+    ".inputs {}\n",
+    inputs
+      .iter()
+      .map(|(_, name)| name.as_str())
+      .collect::<Vec<_>>()
+      .join(" ")
+  ));
+  blif.push_str(&format!(
+    ".outputs {}\n",
+    outputs
+      .iter()
+      .map(|(_, name)| name.as_str())
+      .collect::<Vec<_>>()
+      .join(" ")
+  ));
+
+  for op in sim.ops.iter() {
+    match *op {
+      // The output of a Nand is high when any of its inputs are low
+      Op::Nand(a, b, out) if a == b => {
+        blif.push_str(&format!(".names {} {}\n0 1\n", name(a), name(out)));
+      }
+      Op::Nand(a, b, out) => {
+        blif.push_str(&format!(
+          ".names {} {} {}\n0- 1\n-0 1\n",
+          name(a),
+          name(b),
+          name(out)
+        ));
+      }
+      Op::Buf(a, out) => {
+        blif.push_str(&format!(".names {} {}\n1 1\n", name(a), name(out)));
+      }
+      Op::Inv(a, out) => {
+        blif.push_str(&format!(".names {} {}\n0 1\n", name(a), name(out)));
+      }
+      Op::And(a, b, out) if a == b => {
+        blif.push_str(&format!(".names {} {}\n1 1\n", name(a), name(out)));
+      }
+      Op::And(a, b, out) => {
+        blif.push_str(&format!(
+          ".names {} {} {}\n11 1\n",
+          name(a),
+          name(b),
+          name(out)
+        ));
+      }
+      Op::Or(a, b, out) if a == b => {
+        blif.push_str(&format!(".names {} {}\n1 1\n", name(a), name(out)));
+      }
+      Op::Or(a, b, out) => {
+        blif.push_str(&format!(
+          ".names {} {} {}\n1- 1\n-1 1\n",
+          name(a),
+          name(b),
+          name(out)
+        ));
+      }
+      // Sets are exported with the constants below
+      Op::Set(..) => {}
+    }
+  }
+
+  // Inputs are driven from outside of the model. Every other register that
+  // only a Set or nothing at all writes to is a constant (a table without
+  // rows is a constant low), apart from the immediates without a Set.
+  let sets: HashMap<usize, bool> = sim
+    .ops
+    .iter()
+    .filter_map(|op| match *op {
+      Op::Set(reg, val) => Some((reg, val)),
+      _ => None,
+    })
+    .collect();
+  let driven: HashSet<usize> = sim
+    .ops
+    .iter()
+    .filter(|op| !matches!(op, Op::Set(..)))
+    .map(|op| op_output(*op))
+    .collect();
+  let mut constants: Vec<usize> = sim
+    .ops
+    .iter()
+    .flat_map(|op| op_inputs(*op))
+    .chain(outputs.iter().map(|(reg, _)| *reg))
+    .chain(sets.keys().copied())
+    .filter(|reg| {
+      !driven.contains(reg)
+        && !inputs.iter().any(|(input, _)| input == reg)
+        && (*reg >= sim.immediate_count || sets.contains_key(reg))
+    })
+    .collect();
+  constants.sort_unstable();
+  constants.dedup();
+  for reg in constants {
+    blif.push_str(&format!(".names {}\n", name(reg)));
+    if sets.get(&reg).copied().unwrap_or(sim.registers[reg]) {
+      blif.push_str("1\n");
+    }
+  }
+
+  blif.push_str(".end\n");
+  blif
+}
+
+/// The number of times the layers are swept when ordering them, alternating
+/// between ordering by the layers before and the layers after
+const LAYOUT_SWEEPS: usize = 4;
+
+impl Compiler {
+  /// Orders the registers written in each layer of the last compile to
+  /// reduce the edges that cross between layers
+  ///
+  /// Each sweep sorts the registers of a layer by the average row of the
+  /// registers they read from in earlier layers (or that read them in later
+  /// layers, on the way back), which is the barycenter heuristic. The
+  /// ordering with the fewest crossings is kept, starting with the order
+  /// the ops were scheduled in.
+  pub fn layout(&self) -> Vec<Vec<usize>> {
+    let mut layout: Vec<Vec<usize>> = self
+      .layers
+      .iter()
+      .map(|ops| ops.iter().map(|op| op_output(*op)).collect())
+      .collect();
+    let edges = layout_edges(&self.layers);
+
+    let mut best = layout.clone();
+    let mut fewest = crossings(&layout, &edges);
+    for sweep in 0..LAYOUT_SWEEPS {
+      let down = sweep % 2 == 0;
+      let layers: Vec<usize> = match down {
+        true => (1..layout.len()).collect(),
+        false => (0..layout.len().saturating_sub(1)).rev().collect(),
+      };
+
+      for layer in layers {
+        let places = positions(&layout);
+        let mut barycenters: Vec<(f64, usize)> = layout[layer]
+          .iter()
+          .enumerate()
+          .map(|(row, reg)| {
+            let neighbours: Vec<usize> = edges
+              .iter()
+              .filter_map(|(from, to)| match down {
+                true if to == reg => Some(places[from]),
+                false if from == reg => Some(places[to]),
+                _ => None,
+              })
+              .filter(|(other, _)| match down {
+                true => *other < layer,
+                false => *other > layer,
+              })
+              .map(|(_, row)| row)
+              .collect();
+
+            let barycenter = match neighbours.len() {
+              0 => row as f64,
+              len => neighbours.iter().sum::<usize>() as f64 / len as f64,
+            };
+            (barycenter, *reg)
+          })
+          .collect();
+
+        barycenters.sort_by(|a, b| a.0.total_cmp(&b.0));
+        layout[layer] = barycenters.into_iter().map(|(_, reg)| reg).collect();
+      }
+
+      let count = crossings(&layout, &edges);
+      if count < fewest {
+        best = layout.clone();
+        fewest = count;
+      }
+    }
+
+    best
+  }
+}
+
+/// Gets the edges between the registers written in the layers, from each
+/// register to the registers that read it
+fn layout_edges(layers: &[Vec<Op>]) -> Vec<(usize, usize)> {
+  let written: HashSet<usize> =
+    layers.iter().flatten().map(|op| op_output(*op)).collect();
+
+  layers
+    .iter()
+    .flatten()
+    .filter(|op| !matches!(op, Op::Set(..)))
+    .flat_map(|op| {
+      op_inputs(*op)
+        .into_iter()
+        .filter(|input| written.contains(input))
+        .map(|input| (input, op_output(*op)))
+    })
+    .collect()
+}
+
+/// Gets the layer and row each register is drawn at
+fn positions(layout: &[Vec<usize>]) -> HashMap<usize, (usize, usize)> {
+  layout
+    .iter()
+    .enumerate()
+    .flat_map(|(layer, regs)| {
+      regs
+        .iter()
+        .enumerate()
+        .map(move |(row, reg)| (*reg, (layer, row)))
+    })
+    .collect()
+}
+
+/// Counts the pairs of edges between the same two layers that cross
+fn crossings(layout: &[Vec<usize>], edges: &[(usize, usize)]) -> usize {
+  let places = positions(layout);
+  let edges: Vec<((usize, usize), (usize, usize))> = edges
+    .iter()
+    .map(|(from, to)| (places[from], places[to]))
+    .collect();
+
+  let mut count = 0;
+  for (i, ((from_layer, from_row), (to_layer, to_row))) in
+    edges.iter().enumerate()
+  {
+    for ((other_from_layer, other_from_row), (other_to_layer, other_to_row)) in
+      edges.iter().skip(i + 1)
+    {
+      if from_layer == other_from_layer
+        && to_layer == other_to_layer
+        && from_row.cmp(other_from_row) == other_to_row.cmp(to_row)
+        && from_row != other_from_row
+        && to_row != other_to_row
+      {
+        count += 1;
+      }
+    }
+  }
+
+  count
+}
+
+/// Exports the layers of the last compile as an SVG schematic
+///
+/// Layers are laid out left-to-right in the order of [`Compiler::layout`],
+/// Set ops are drawn as circles and Nand ops as boxes, with a line from each
+/// register to the Nands that read it.
+pub fn to_svg(compiler: &Compiler) -> String {
+  const SPACING: usize = 80;
+  const SIZE: usize = 40;
+
+  let position = |layer: usize, row: usize| {
+    (layer * SPACING + SPACING / 2, row * SPACING + SPACING / 2)
+  };
+
+  // Where each register is drawn
+  let mut positions: HashMap<usize, (usize, usize)> = HashMap::new();
+  for (layer, regs) in compiler.layout().iter().enumerate() {
+    for (row, reg) in regs.iter().enumerate() {
+      positions.insert(*reg, position(layer, row));
+    }
+  }
+
+  let width = compiler.layers.len() * SPACING;
+  let height = compiler
+    .layers
+    .iter()
+    .map(|ops| ops.len())
+    .max()
+    .unwrap_or(0)
+    * SPACING;
+
+  let mut svg = String::new();
+  svg.push_str(&format!(
+    "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+    width, height
+  ));
+
+  // Edges first so the nodes are drawn on top of them
+  for op in compiler.layers.iter().flatten() {
+    let (x2, y2) = positions[&op_output(*op)];
+    for input in op_inputs(*op) {
+      if let Some((x1, y1)) = positions.get(&input) {
+        svg.push_str(&format!(
+          "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\" />\n",
+          x1, y1, x2, y2
+        ));
+      }
+    }
+  }
+
+  for op in compiler.layers.iter().flatten() {
+    match *op {
+      op @ (Op::Nand(..)
+      | Op::Buf(..)
+      | Op::Inv(..)
+      | Op::And(..)
+      | Op::Or(..)) => {
+        let out = op_output(op);
+        let (x, y) = positions[&out];
+        svg.push_str(&format!(
+          "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\" stroke=\"black\" />\n",
+          x - SIZE / 2,
+          y - SIZE / 2,
+          SIZE,
+          SIZE
+        ));
+        svg.push_str(&format!(
+          "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">r{}</text>\n",
+          x, y, out
+        ));
+      }
+      Op::Set(reg, _) => {
+        let (x, y) = positions[&reg];
+        svg.push_str(&format!(
+          "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"white\" stroke=\"black\" />\n",
+          x,
+          y,
+          SIZE / 2
+        ));
+        svg.push_str(&format!(
+          "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">r{}</text>\n",
+          x, y, reg
+        ));
+      }
+    }
+  }
+
+  svg.push_str("</svg>\n");
+  svg
+}
+
+/// Exports the ops of the last compile as a DOT graph, with a cluster around
+/// the ops of each gate
+///
+/// Each cluster is labeled with [`crate::Gate::dot_label`]. Ops that don't
+/// belong to a gate, such as the Sets of the immediates, are drawn outside
+/// of the clusters.
+pub fn to_dot_clustered(compiler: &Compiler) -> String {
+  let node = |op: Op| {
+    let label = match op {
+      Op::Nand(a, b, out) => format!("r{} = Nand(r{}, r{})", out, a, b),
+      Op::Set(reg, val) => format!("r{} = Set({})", reg, val),
+      Op::Buf(a, out) => format!("r{} = Buf(r{})", out, a),
+      Op::Inv(a, out) => format!("r{} = Inv(r{})", out, a),
+      Op::And(a, b, out) => format!("r{} = And(r{}, r{})", out, a, b),
+      Op::Or(a, b, out) => format!("r{} = Or(r{}, r{})", out, a, b),
+    };
+    format!("r{} [label = \"{}\"]\n", op_output(op), label)
+  };
+
+  // The op that writes to each register
+  let writers: HashMap<usize, Op> = compiler
+    .layers
+    .iter()
+    .flatten()
+    .map(|op| (op_output(*op), *op))
+    .collect();
+
+  let mut dot = String::from("digraph {\n");
+  let mut grouped: HashSet<usize> = HashSet::new();
+  for (i, (label, outputs)) in compiler.gate_groups.iter().enumerate() {
+    dot.push_str(&format!("  subgraph cluster_{} {{\n", i));
+    dot.push_str(&format!("    label = \"{}\"\n", label));
+    for out in outputs {
+      if let Some(op) = writers.get(out) {
+        if grouped.insert(*out) {
+          dot.push_str(&format!("    {}", node(*op)));
+        }
+      }
+    }
+    dot.push_str("  }\n");
+  }
+
+  for op in compiler.layers.iter().flatten() {
+    if !grouped.contains(&op_output(*op)) {
+      dot.push_str(&format!("  {}", node(*op)));
+    }
+  }
+
+  for op in compiler.layers.iter().flatten() {
+    for input in op_inputs(*op) {
+      if writers.contains_key(&input) {
+        dot.push_str(&format!("  r{} -> r{}\n", input, op_output(*op)));
+      }
+    }
+  }
+
+  dot.push_str("}\n");
+  dot
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{And, Compiler, Constant, FullAdder, Gate, Not};
+
+  #[test]
+  /// Test that an And gate exports its inputs, outputs, and Nand tables
+  fn blif_and_gate() {
+    let mut compiler = Compiler::new(2);
+    let [a, b] = [0, 1];
+
+    let and = And {
+      a,
+      b,
+      out: compiler.alloc(),
+    };
+
+    let simulation = compiler.compile(vec![&Gate::from(and)]).unwrap();
+    let blif = to_blif(
+      &simulation,
+      "and",
+      &[(a, "a".into()), (b, "b".into())],
+      &[(and.out, "out".into())],
+    );
+
+    assert!(blif.starts_with(".model and\n"));
+    assert!(blif.contains(".inputs a b\n"));
+    assert!(blif.contains(".outputs out\n"));
+    assert!(blif.contains(".names a b r3\n0- 1\n-0 1\n"));
+    assert!(blif.contains(".names r3 out\n0 1\n"));
+    assert!(blif.ends_with(".end\n"));
+  }
+
+  #[test]
+  /// Test that the outputs of constants are exported as constant tables, so
+  /// the registers reading them are driven
+  fn blif_constant() {
+    let mut compiler = Compiler::new(1);
+    let [high, low] = [compiler.alloc(), compiler.alloc()];
+    let and = And {
+      a: 0,
+      b: high,
+      out: compiler.alloc(),
+    };
+    let gates = [
+      Gate::from(Constant {
+        value: true,
+        out: high,
+      }),
+      Gate::from(Constant {
+        value: false,
+        out: low,
+      }),
+      Gate::from(and),
+    ];
+
+    let simulation = compiler.compile(gates.iter().collect()).unwrap();
+    let blif = to_blif(
+      &simulation,
+      "constant",
+      &[(0, "a".into())],
+      &[(and.out, "out".into()), (low, "low".into())],
+    );
+
+    assert!(blif.contains(".names a r1 "));
+    assert!(blif.ends_with(".names r1\n1\n.names low\n.end\n"));
+  }
+
+  #[test]
+  /// Test that the full adder exports a box for each of its Nand ops
+  fn svg_full_adder() {
+    let mut compiler = Compiler::new(3);
+    let [a, b, cin] = [0, 1, 2];
+
+    let s = compiler.alloc();
+    let cout = compiler.alloc();
+
+    let full_adder = FullAdder { a, b, cin, s, cout };
+
+    let simulation = compiler.compile(vec![&Gate::from(full_adder)]).unwrap();
+    let nands = simulation
+      .ops
+      .iter()
+      .filter(|op| matches!(op, Op::Nand(..)))
+      .count();
+
+    let svg = to_svg(&compiler);
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.ends_with("</svg>\n"));
+    assert_eq!(svg.matches("<rect").count(), nands);
+    assert_eq!(svg.matches("<circle").count(), 3);
+  }
+
+  #[test]
+  /// Test that ordering the layers of a reconvergent circuit removes the
+  /// crossings of the scheduled order
+  fn layout_reduces_crossings() {
+    let mut compiler = Compiler::new(3);
+    let [a, b, c] = [0, 1, 2];
+    let [x, y, z, out] = [(); 4].map(|_| compiler.alloc());
+    compiler.merged = vec![
+      Op::Nand(c, c, x),
+      Op::Nand(b, c, y),
+      Op::Nand(a, a, z),
+      Op::Nand(x, z, out),
+    ];
+    compiler.compile(vec![]).unwrap();
+
+    let naive: Vec<Vec<usize>> = compiler
+      .layers
+      .iter()
+      .map(|ops| ops.iter().map(|op| op_output(*op)).collect())
+      .collect();
+    let edges = layout_edges(&compiler.layers);
+    let layout = compiler.layout();
+
+    assert_eq!(layout[1], vec![z, y, x]);
+    assert!(crossings(&naive, &edges) > 0);
+    assert_eq!(crossings(&layout, &edges), 0);
+
+    // Every register is still laid out in its layer
+    for (naive, layer) in naive.iter().zip(layout.iter()) {
+      let mut naive = naive.clone();
+      let mut layer = layer.clone();
+      naive.sort();
+      layer.sort();
+      assert_eq!(naive, layer);
+    }
+  }
+
+  #[test]
+  /// Test that each gate gets a cluster labeled with the gate
+  fn dot_clusters() {
+    let mut compiler = Compiler::new(2);
+    let and = And {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let not = Not {
+      a: and.out,
+      out: compiler.alloc(),
+    };
+
+    compiler
+      .compile(vec![&Gate::from(and), &Gate::from(not)])
+      .unwrap();
+    let dot = to_dot_clustered(&compiler);
+
+    assert!(dot.starts_with("digraph {\n"));
+    assert_eq!(dot.matches("subgraph cluster_").count(), 2);
+    assert!(dot.contains("label = \"And(r0, r1) -> r2\""));
+    assert!(dot.contains("label = \"Not(r2) -> r3\""));
+
+    // Both Nands of the And are inside its cluster
+    let and_cluster = &dot[dot.find("cluster_0").unwrap()..];
+    let and_cluster = &and_cluster[..and_cluster.find("}").unwrap()];
+    assert_eq!(and_cluster.matches("= Nand(").count(), 2);
+    assert!(dot.contains("r2 -> r3\n"));
+  }
+}
@@ -0,0 +1,186 @@
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Compiler, Gate};
+
+/// The newest version of the circuit file format
+pub const CIRCUIT_FILE_VERSION: u32 = 1;
+
+/// A circuit saved to disk, which keeps working as the crate changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitFile {
+  /// The version of the format the file was saved with
+  pub version: u32,
+
+  /// The number of immediates, which are the first registers
+  pub immediate_count: usize,
+
+  /// The gates of the circuit
+  pub gates: Vec<Gate>,
+
+  /// Names of registers, such as the inputs and outputs
+  #[serde(default)]
+  pub labels: HashMap<usize, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileError {
+  /// The file couldn't be read or written
+  Io(String),
+
+  /// The file isn't a valid circuit file
+  Json(String),
+
+  /// The file was saved with a newer version than this crate supports
+  UnsupportedVersion { version: u32, supported: u32 },
+}
+
+impl fmt::Display for FileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FileError::Io(err) => write!(f, "couldn't access the file: {}", err),
+      FileError::Json(err) => write!(f, "invalid circuit file: {}", err),
+      FileError::UnsupportedVersion { version, supported } => {
+        write!(
+          f,
+          "file version {} is newer than the supported version {}",
+          version, supported
+        )
+      }
+    }
+  }
+}
+
+impl std::error::Error for FileError {}
+
+impl CircuitFile {
+  /// Creates a circuit file with the current version
+  pub fn new(
+    immediate_count: usize,
+    gates: Vec<Gate>,
+    labels: HashMap<usize, String>,
+  ) -> Self {
+    Self {
+      version: CIRCUIT_FILE_VERSION,
+      immediate_count,
+      gates,
+      labels,
+    }
+  }
+
+  /// Saves the circuit as JSON
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FileError> {
+    let json = serde_json::to_string_pretty(self)
+      .map_err(|err| FileError::Json(err.to_string()))?;
+
+    fs::write(path, json).map_err(|err| FileError::Io(err.to_string()))
+  }
+
+  /// Loads a circuit from JSON
+  ///
+  /// The version is checked before anything else, so files from a newer
+  /// version are rejected even if their layout has changed.
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, FileError> {
+    let json =
+      fs::read_to_string(path).map_err(|err| FileError::Io(err.to_string()))?;
+    let value: serde_json::Value = serde_json::from_str(&json)
+      .map_err(|err| FileError::Json(err.to_string()))?;
+
+    let version = value
+      .get("version")
+      .and_then(|version| version.as_u64())
+      .ok_or_else(|| FileError::Json("missing version".into()))?;
+    if version > CIRCUIT_FILE_VERSION as u64 {
+      return Err(FileError::UnsupportedVersion {
+        version: u32::try_from(version).unwrap_or(u32::MAX),
+        supported: CIRCUIT_FILE_VERSION,
+      });
+    }
+
+    serde_json::from_value(value)
+      .map_err(|err| FileError::Json(err.to_string()))
+  }
+
+  /// Creates a compiler for the circuit that has allocated every register
+  /// the gates use, and labels them with the circuit's labels
+  pub fn compiler(&self) -> Compiler {
+    let mut compiler = Compiler::new(self.immediate_count);
+    let count = self
+      .gates
+      .iter()
+      .flat_map(|gate| gate.inputs().into_iter().chain(gate.outputs()))
+      .map(|reg| reg + 1)
+      .max()
+      .unwrap_or(0);
+    compiler
+      .incrementer
+      .skip(count.saturating_sub(self.immediate_count));
+    compiler.labels = self.labels.clone();
+
+    compiler
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+
+  use crate::{And, Not};
+
+  use super::*;
+
+  #[test]
+  /// Test that a saved circuit loads back the same
+  fn round_trip() {
+    let path = env::temp_dir().join("complogic_round_trip.json");
+    let file = CircuitFile::new(
+      2,
+      vec![
+        Gate::from(And { a: 0, b: 1, out: 2 }),
+        Gate::from(Not { a: 2, out: 3 }),
+      ],
+      HashMap::from([(0, "a".to_string()), (3, "nand".to_string())]),
+    );
+
+    file.save(&path).unwrap();
+    let loaded = CircuitFile::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.version, CIRCUIT_FILE_VERSION);
+    assert_eq!(loaded.immediate_count, 2);
+    assert_eq!(format!("{:?}", loaded.gates), format!("{:?}", file.gates));
+    assert_eq!(loaded.labels, file.labels);
+
+    let mut simulation = loaded
+      .compiler()
+      .compile(loaded.gates.iter().collect())
+      .unwrap();
+    simulation.run(&[true, true]);
+    assert!(!simulation.registers[3]);
+    assert_eq!(simulation.labels, file.labels);
+  }
+
+  #[test]
+  /// Test that a file from a newer version is rejected
+  fn reject_newer_version() {
+    let path = env::temp_dir().join("complogic_newer_version.json");
+    let json = format!(
+      "{{\"version\": {}, \"wires\": []}}",
+      CIRCUIT_FILE_VERSION + 1
+    );
+    fs::write(&path, json).unwrap();
+
+    let err = CircuitFile::load(&path).unwrap_err();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+      err,
+      FileError::UnsupportedVersion {
+        version: CIRCUIT_FILE_VERSION + 1,
+        supported: CIRCUIT_FILE_VERSION,
+      }
+    );
+  }
+}
@@ -0,0 +1,131 @@
+use crate::{synthesize_from, Compiler, DFlipFlop, Gate};
+
+/// A finite-state machine, with a state register that moves to the next
+/// state on the rising edge of `clk`
+///
+/// Each transition maps the current state bits followed by the values of
+/// `inputs` to the next state bits followed by the outputs. Outputs that
+/// only depend on the state make a Moore machine, otherwise it's a Mealy
+/// machine. Combinations that aren't listed go to state zero with low
+/// outputs, which is also the state the machine starts in.
+#[derive(Debug, Clone)]
+pub struct Fsm {
+  pub clk: usize,
+  pub inputs: Vec<usize>,
+  pub state_bits: usize,
+  pub output_count: usize,
+  pub transitions: Vec<(Vec<bool>, Vec<bool>)>,
+}
+
+/// The gates of a built [`Fsm`] and the registers it exposes
+#[derive(Debug, Clone)]
+pub struct FsmCircuit {
+  pub gates: Vec<Gate>,
+
+  /// The state register, with the first state bit first
+  pub state: Vec<usize>,
+
+  /// The outputs, in the order of the transitions
+  pub outputs: Vec<usize>,
+}
+
+impl Fsm {
+  /// Synthesizes the next state and output logic and the state register
+  pub fn build(&self, compiler: &mut Compiler) -> FsmCircuit {
+    assert!(self.state_bits > 0, "a state machine needs a state bit");
+
+    let state: Vec<usize> =
+      (0..self.state_bits).map(|_| compiler.alloc()).collect();
+    let inputs: Vec<usize> =
+      state.iter().chain(self.inputs.iter()).copied().collect();
+
+    let mut gates: Vec<Gate> = vec![];
+
+    // Each next state bit and output is its own function of the state and
+    // the inputs, where the last gate of each is its output
+    let mut columns: Vec<usize> = vec![];
+    for column in 0..self.state_bits + self.output_count {
+      let rows: Vec<(Vec<bool>, Option<bool>)> = self
+        .transitions
+        .iter()
+        .map(|(from, to)| {
+          assert_eq!(
+            to.len(),
+            self.state_bits + self.output_count,
+            "transitions need a value for each state bit and output"
+          );
+          (from.clone(), Some(to[column]))
+        })
+        .collect();
+
+      let function = synthesize_from(&inputs, &rows, compiler)
+        .expect("transitions need a value for each state bit and input");
+      columns.extend(
+        function
+          .last()
+          .and_then(|gate| gate.outputs().first().copied()),
+      );
+      gates.extend(function);
+    }
+
+    for (q, d) in state.iter().zip(columns.iter()) {
+      gates.push(Gate::from(DFlipFlop {
+        d: *d,
+        clk: self.clk,
+        q: *q,
+      }));
+    }
+
+    FsmCircuit {
+      gates,
+      state,
+      outputs: columns[self.state_bits..].to_vec(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  /// Test that a toggle machine flips its state on each clock while its
+  /// input is high
+  fn toggle_fsm() {
+    let mut compiler = Compiler::new(2);
+    let [clk, toggle] = [0, 1];
+
+    // The state flips when toggle is high and is also the output
+    let fsm = Fsm {
+      clk,
+      inputs: vec![toggle],
+      state_bits: 1,
+      output_count: 1,
+      transitions: vec![
+        (vec![false, false], vec![false, false]),
+        (vec![false, true], vec![true, false]),
+        (vec![true, false], vec![true, true]),
+        (vec![true, true], vec![false, true]),
+      ],
+    };
+    let circuit = fsm.build(&mut compiler);
+    let mut simulation =
+      compiler.compile(circuit.gates.iter().collect()).unwrap();
+
+    let out = circuit.outputs[0];
+    let mut clock = |toggle: bool| {
+      assert!(simulation.run_until_stable(&[false, toggle], 100));
+      assert!(simulation.run_until_stable(&[true, toggle], 100));
+      (
+        simulation.registers[circuit.state[0]],
+        simulation.registers[out],
+      )
+    };
+
+    assert_eq!(clock(false), (false, false));
+    assert_eq!(clock(true), (true, true));
+    assert_eq!(clock(false), (true, true));
+    assert_eq!(clock(true), (false, false));
+    assert_eq!(clock(true), (true, true));
+  }
+}
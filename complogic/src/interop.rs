@@ -0,0 +1,225 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{gate_from_spec, Compiler, Gate};
+
+/// A circuit in the simplified format used by educational tools
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitJson {
+  /// The number of immediates, which are the first registers
+  pub immediates: usize,
+
+  /// The gates of the circuit
+  pub gates: Vec<GateJson>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GateJson {
+  /// The kind of gate, in snake case (e.g. `full_adder`)
+  #[serde(rename = "type")]
+  pub kind: String,
+
+  /// The registers the gate reads from
+  pub inputs: Vec<usize>,
+
+  /// The registers the gate writes to
+  pub outputs: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+  /// The JSON couldn't be parsed
+  Json(String),
+
+  /// The gate at the index has an unknown type or the wrong number of
+  /// inputs or outputs for its type
+  InvalidGate { index: usize, kind: String },
+
+  /// A register is outside of the registers the circuit allocates
+  RegisterOutOfRange { register: usize, count: usize },
+}
+
+impl fmt::Display for ImportError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ImportError::Json(err) => write!(f, "invalid JSON: {}", err),
+      ImportError::InvalidGate { index, kind } => {
+        write!(f, "gate {} has an invalid {} wiring", index, kind)
+      }
+      ImportError::RegisterOutOfRange { register, count } => {
+        write!(
+          f,
+          "register {} is out of range of the {} registers",
+          register, count
+        )
+      }
+    }
+  }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Imports a circuit from JSON, returning its gates and a compiler that has
+/// allocated their outputs
+///
+/// The first registers are the immediates, followed by one register for each
+/// gate output, so every register has to be below the immediate count plus
+/// the output count.
+pub fn import_json(json: &str) -> Result<(Vec<Gate>, Compiler), ImportError> {
+  let circuit: CircuitJson = serde_json::from_str(json)
+    .map_err(|err| ImportError::Json(err.to_string()))?;
+
+  let outputs: usize =
+    circuit.gates.iter().map(|gate| gate.outputs.len()).sum();
+  let count = circuit.immediates + outputs;
+
+  let gates = circuit
+    .gates
+    .iter()
+    .enumerate()
+    .map(|(index, gate)| {
+      if let Some(register) = gate
+        .inputs
+        .iter()
+        .chain(gate.outputs.iter())
+        .find(|register| **register >= count)
+      {
+        return Err(ImportError::RegisterOutOfRange {
+          register: *register,
+          count,
+        });
+      }
+
+      to_gate(gate).ok_or_else(|| ImportError::InvalidGate {
+        index,
+        kind: gate.kind.clone(),
+      })
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let mut compiler = Compiler::new(circuit.immediates);
+  compiler.incrementer.skip(outputs);
+
+  Ok((gates, compiler))
+}
+
+/// The type of each gate in the JSON format, with its spec name
+const JSON_KINDS: [(&str, &str); 27] = [
+  ("nand", "Nand"),
+  ("not", "Not"),
+  ("buffer", "Buffer"),
+  ("and", "And"),
+  ("or", "Or"),
+  ("nor", "Nor"),
+  ("xor", "Xor"),
+  ("and3", "And3"),
+  ("or3", "Or3"),
+  ("encoder_4_to_2", "Encoder4to2"),
+  ("tri_state", "TriState"),
+  ("mux2", "Mux2"),
+  ("mux_wide", "MuxWide"),
+  ("rs_latch", "RSLatch"),
+  ("nand_rs_latch", "NandRSLatch"),
+  ("gated_sr_latch", "GatedSRLatch"),
+  ("d_latch", "DLatch"),
+  ("d_flip_flop", "DFlipFlop"),
+  ("d_flip_flop_sr", "DFlipFlopSR"),
+  ("shift_register", "ShiftRegister"),
+  ("async_counter", "AsyncCounter"),
+  ("half_adder", "HalfAdder"),
+  ("full_adder", "FullAdder"),
+  ("four_bit_adder", "FourBitAdder"),
+  ("ripple_carry_adder", "RippleCarryAdder"),
+  ("magnitude_comparator", "MagnitudeComparator"),
+  ("multiplier", "Multiplier"),
+];
+
+/// Builds a gate from its JSON, if it's wired correctly for its type
+fn to_gate(gate: &GateJson) -> Option<Gate> {
+  let (_, name) = JSON_KINDS.iter().find(|(kind, _)| *kind == gate.kind)?;
+
+  gate_from_spec(name, &gate.inputs, &gate.outputs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{from_bits, to_bits, FullAdder};
+
+  #[test]
+  /// Test that a full adder built from JSON matches the native full adder
+  fn import_full_adder() {
+    let json = r#"{
+      "immediates": 3,
+      "gates": [
+        { "type": "xor", "inputs": [0, 1], "outputs": [3] },
+        { "type": "xor", "inputs": [3, 2], "outputs": [4] },
+        { "type": "and", "inputs": [0, 1], "outputs": [5] },
+        { "type": "and", "inputs": [3, 2], "outputs": [6] },
+        { "type": "or", "inputs": [5, 6], "outputs": [7] }
+      ]
+    }"#;
+
+    let (gates, mut compiler) = import_json(json).unwrap();
+    let [s, cout] = [4, 7];
+    let mut imported = compiler.compile(gates.iter().collect()).unwrap();
+
+    let mut compiler = Compiler::new(3);
+    let full_adder = FullAdder {
+      a: 0,
+      b: 1,
+      cin: 2,
+      s: compiler.alloc(),
+      cout: compiler.alloc(),
+    };
+    let mut native = compiler.compile(vec![&Gate::from(full_adder)]).unwrap();
+
+    for inputs in 0..8 {
+      let inputs = to_bits(inputs, 3);
+      imported.run(&inputs);
+      native.run(&inputs);
+
+      assert_eq!(imported.registers[s], native.registers[full_adder.s]);
+      assert_eq!(imported.registers[cout], native.registers[full_adder.cout]);
+    }
+  }
+
+  #[test]
+  /// Test that a 2-bit multiplier is imported from JSON
+  fn import_multiplier() {
+    let json = r#"{
+      "immediates": 4,
+      "gates": [
+        { "type": "multiplier", "inputs": [0, 1, 2, 3], "outputs": [4, 5, 6, 7] }
+      ]
+    }"#;
+
+    let (gates, mut compiler) = import_json(json).unwrap();
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+
+    // The numbers are least significant bit first, 3 * 2 = 6
+    simulation.run(&[true, true, false, true]);
+    let mut product: Vec<bool> =
+      (4..8).map(|reg| simulation.registers[reg]).collect();
+    product.reverse();
+    assert_eq!(from_bits(&product), 6);
+  }
+
+  #[test]
+  /// Test that registers outside of the circuit are rejected
+  fn import_out_of_range() {
+    let json = r#"{
+      "immediates": 2,
+      "gates": [{ "type": "and", "inputs": [0, 5], "outputs": [2] }]
+    }"#;
+
+    assert_eq!(
+      import_json(json).err(),
+      Some(ImportError::RegisterOutOfRange {
+        register: 5,
+        count: 3
+      })
+    );
+  }
+}
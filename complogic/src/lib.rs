@@ -1,7 +1,33 @@
+mod analysis;
 mod compile;
+mod diff;
+mod export;
+mod file;
+mod fsm;
 mod gates;
+mod interop;
+mod library;
+mod logic;
+mod netlist;
 mod simulation;
+mod spec;
+mod synthesis;
+mod testbench;
+mod util;
 
+pub use analysis::*;
 pub use compile::*;
+pub use diff::*;
+pub use export::*;
+pub use file::*;
+pub use fsm::*;
 pub use gates::*;
+pub use interop::*;
+pub use library::*;
+pub use logic::*;
+pub use netlist::*;
 pub use simulation::*;
+pub use spec::*;
+pub use synthesis::*;
+pub use testbench::*;
+pub use util::*;
@@ -0,0 +1,305 @@
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{gate_from_spec, Gate, Incrementer, Ops, ParseError, UserGate};
+
+/// A file of gate definitions, which can use the gates defined before them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LibraryFile {
+  pub gates: Vec<GateDefinition>,
+}
+
+/// A composite gate, wired up from other gates by named nets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GateDefinition {
+  /// The name the gate is instantiated by
+  pub name: String,
+
+  /// The nets the gate reads from
+  pub inputs: Vec<String>,
+
+  /// The nets the gate writes to
+  pub outputs: Vec<String>,
+
+  /// The gates inside of the gate
+  pub gates: Vec<SubGate>,
+}
+
+/// A gate inside of a definition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubGate {
+  /// The kind of gate (see [`crate::gate_from_spec`]), or the name of a gate
+  /// defined before it
+  pub kind: String,
+
+  /// The nets the gate reads from
+  pub inputs: Vec<String>,
+
+  /// The nets the gate writes to
+  pub outputs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LibraryError {
+  /// The file couldn't be read
+  Io(String),
+
+  /// The file isn't a valid library
+  Json(String),
+
+  /// A gate is already defined with the name
+  Duplicate(String),
+
+  /// A gate inside of a definition couldn't be built
+  InvalidGate {
+    definition: String,
+    index: usize,
+    err: ParseError,
+  },
+}
+
+impl fmt::Display for LibraryError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LibraryError::Io(err) => write!(f, "couldn't read the library: {}", err),
+      LibraryError::Json(err) => write!(f, "invalid library: {}", err),
+      LibraryError::Duplicate(name) => write!(f, "{} is already defined", name),
+      LibraryError::InvalidGate {
+        definition,
+        index,
+        err,
+      } => write!(f, "gate {} of {}: {}", index, definition, err),
+    }
+  }
+}
+
+impl std::error::Error for LibraryError {}
+
+/// The gates defined by users, which are instantiated like built-in gates
+#[derive(Debug, Clone, Default)]
+pub struct GateLibrary {
+  /// Each gate by its name, wired to the registers of its template
+  pub gates: HashMap<String, UserGate>,
+}
+
+impl GateLibrary {
+  /// Creates an empty library
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Loads a library from a JSON file
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, LibraryError> {
+    let json = fs::read_to_string(path)
+      .map_err(|err| LibraryError::Io(err.to_string()))?;
+
+    Self::from_json(&json)
+  }
+
+  /// Builds a library from JSON, defining its gates in order
+  pub fn from_json(json: &str) -> Result<Self, LibraryError> {
+    let file: LibraryFile = serde_json::from_str(json)
+      .map_err(|err| LibraryError::Json(err.to_string()))?;
+
+    let mut library = Self::new();
+    for definition in file.gates.iter() {
+      library.define(definition)?;
+    }
+
+    Ok(library)
+  }
+
+  /// Lowers a definition into a template and adds it to the library
+  ///
+  /// The inputs and outputs are the first nets, then every other net in the
+  /// order it's first used.
+  pub fn define(
+    &mut self,
+    definition: &GateDefinition,
+  ) -> Result<(), LibraryError> {
+    if self.gates.contains_key(&definition.name) {
+      return Err(LibraryError::Duplicate(definition.name.clone()));
+    }
+
+    let mut nets: HashMap<&str, usize> = HashMap::new();
+    let names = definition
+      .inputs
+      .iter()
+      .chain(definition.outputs.iter())
+      .chain(
+        definition
+          .gates
+          .iter()
+          .flat_map(|gate| gate.inputs.iter().chain(gate.outputs.iter())),
+      );
+    for name in names {
+      let next = nets.len();
+      nets.entry(name.as_str()).or_insert(next);
+    }
+
+    // Registers inside of the sub-gates come after every net
+    let mut incrementer = Incrementer::set(nets.len());
+    let mut template: Ops = vec![];
+    for (index, gate) in definition.gates.iter().enumerate() {
+      let registers = |names: &[String]| -> Vec<usize> {
+        names.iter().map(|name| nets[name.as_str()]).collect()
+      };
+      let gate = self
+        .instantiate(
+          &gate.kind,
+          &registers(&gate.inputs),
+          &registers(&gate.outputs),
+        )
+        .map_err(|err| LibraryError::InvalidGate {
+          definition: definition.name.clone(),
+          index,
+          err,
+        })?;
+
+      template.extend(gate.create(&mut incrementer));
+    }
+
+    let input_count = definition.inputs.len();
+    let output_count = definition.outputs.len();
+    self.gates.insert(
+      definition.name.clone(),
+      UserGate {
+        name: definition.name.clone(),
+        template,
+        inputs: (0..input_count).collect(),
+        outputs: (input_count..input_count + output_count).collect(),
+      },
+    );
+
+    Ok(())
+  }
+
+  /// Builds a gate from its name and its registers, from the library or
+  /// otherwise the built-in gates (see [`crate::gate_from_spec`])
+  pub fn instantiate(
+    &self,
+    name: &str,
+    inputs: &[usize],
+    outputs: &[usize],
+  ) -> Result<Gate, ParseError> {
+    let Some(user_gate) = self.gates.get(name) else {
+      return gate_from_spec(name, inputs, outputs);
+    };
+
+    if inputs.len() != user_gate.inputs.len()
+      || outputs.len() != user_gate.outputs.len()
+    {
+      return Err(ParseError::Arity {
+        name: name.into(),
+        inputs: inputs.len(),
+        outputs: outputs.len(),
+      });
+    }
+
+    Ok(Gate::from(UserGate {
+      inputs: inputs.to_vec(),
+      outputs: outputs.to_vec(),
+      ..user_gate.clone()
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Compiler;
+
+  /// An Xor built from four Nands
+  const XOR_LIBRARY: &str = r#"{
+    "gates": [
+      {
+        "name": "NandXor",
+        "inputs": ["a", "b"],
+        "outputs": ["out"],
+        "gates": [
+          { "kind": "Nand", "inputs": ["a", "b"], "outputs": ["ab"] },
+          { "kind": "Nand", "inputs": ["a", "ab"], "outputs": ["x"] },
+          { "kind": "Nand", "inputs": ["b", "ab"], "outputs": ["y"] },
+          { "kind": "Nand", "inputs": ["x", "y"], "outputs": ["out"] }
+        ]
+      }
+    ]
+  }"#;
+
+  #[test]
+  /// Test that a user-defined Xor simulates as an Xor
+  fn user_xor() {
+    let library = GateLibrary::from_json(XOR_LIBRARY).unwrap();
+
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+    let gate = library.instantiate("NandXor", &[0, 1], &[out]).unwrap();
+    assert_eq!(gate.cost(), 4);
+    let mut simulation = compiler.compile(vec![&gate]).unwrap();
+
+    for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+      simulation.run(&[a, b]);
+      assert_eq!(simulation.registers[out], a != b, "{} xor {}", a, b);
+
+      let mut registers = simulation.registers.clone();
+      registers[out] = !registers[out];
+      gate.eval(&mut registers);
+      assert_eq!(registers[out], a != b, "{} xor {}", a, b);
+    }
+  }
+
+  #[test]
+  /// Test that definitions can use gates defined before them, and that bad
+  /// wirings are rejected
+  fn nested_definitions() {
+    let mut library = GateLibrary::from_json(XOR_LIBRARY).unwrap();
+    let nets =
+      |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    library
+      .define(&GateDefinition {
+        name: "Parity3".into(),
+        inputs: nets(&["a", "b", "c"]),
+        outputs: nets(&["out"]),
+        gates: vec![
+          SubGate {
+            kind: "NandXor".into(),
+            inputs: nets(&["a", "b"]),
+            outputs: nets(&["ab"]),
+          },
+          SubGate {
+            kind: "Xor".into(),
+            inputs: nets(&["ab", "c"]),
+            outputs: nets(&["out"]),
+          },
+        ],
+      })
+      .unwrap();
+
+    let mut compiler = Compiler::new(3);
+    let out = compiler.alloc();
+    let gate = library.instantiate("Parity3", &[0, 1, 2], &[out]).unwrap();
+    let mut simulation = compiler.compile(vec![&gate]).unwrap();
+    for value in 0..8 {
+      let bits = crate::to_bits(value, 3);
+      simulation.run(&bits);
+      assert_eq!(simulation.registers[out], value.count_ones() % 2 == 1);
+    }
+
+    assert_eq!(
+      library.instantiate("Parity3", &[0, 1], &[out]).unwrap_err(),
+      ParseError::Arity {
+        name: "Parity3".into(),
+        inputs: 2,
+        outputs: 1,
+      }
+    );
+    assert!(matches!(
+      GateLibrary::from_json(
+        r#"{ "gates": [{ "name": "Bad", "inputs": ["a"], "outputs": ["out"],
+          "gates": [{ "kind": "Mux", "inputs": ["a"], "outputs": ["out"] }] }] }"#
+      ),
+      Err(LibraryError::InvalidGate { index: 0, .. })
+    ));
+  }
+}
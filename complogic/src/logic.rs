@@ -0,0 +1,108 @@
+use std::{fmt, ops::Not};
+
+use serde::{Deserialize, Serialize};
+
+/// A value of a register in three-valued simulation, where `X` is unknown
+#[derive(
+  Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize,
+)]
+pub enum Logic {
+  Zero,
+  One,
+
+  /// Either value, such as an uninitialized register or one driven both ways
+  #[default]
+  X,
+}
+
+impl Logic {
+  /// Gets the value as a bool, if it's known
+  pub fn to_bool(self) -> Option<bool> {
+    match self {
+      Logic::Zero => Some(false),
+      Logic::One => Some(true),
+      Logic::X => None,
+    }
+  }
+
+  /// A Nand, which is high whenever an input is low even if the other input
+  /// is unknown
+  pub fn nand(self, other: Logic) -> Logic {
+    !self.and(other)
+  }
+
+  /// An And, which is low whenever an input is low even if the other input
+  /// is unknown
+  pub fn and(self, other: Logic) -> Logic {
+    match (self, other) {
+      (Logic::Zero, _) | (_, Logic::Zero) => Logic::Zero,
+      (Logic::One, Logic::One) => Logic::One,
+      _ => Logic::X,
+    }
+  }
+
+  /// An Or, which is high whenever an input is high even if the other input
+  /// is unknown
+  pub fn or(self, other: Logic) -> Logic {
+    !(!self).and(!other)
+  }
+}
+
+impl Not for Logic {
+  type Output = Logic;
+
+  /// Inverts the value, leaving it unknown if it's unknown
+  fn not(self) -> Logic {
+    match self {
+      Logic::Zero => Logic::One,
+      Logic::One => Logic::Zero,
+      Logic::X => Logic::X,
+    }
+  }
+}
+
+impl From<bool> for Logic {
+  fn from(value: bool) -> Self {
+    match value {
+      false => Logic::Zero,
+      true => Logic::One,
+    }
+  }
+}
+
+impl fmt::Display for Logic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Logic::Zero => write!(f, "0"),
+      Logic::One => write!(f, "1"),
+      Logic::X => write!(f, "X"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  /// Test that a Nand is only unknown when the known input doesn't decide it
+  fn nand() {
+    let cases = [
+      (Logic::Zero, Logic::X, Logic::One),
+      (Logic::X, Logic::Zero, Logic::One),
+      (Logic::One, Logic::X, Logic::X),
+      (Logic::X, Logic::X, Logic::X),
+      (Logic::One, Logic::One, Logic::Zero),
+    ];
+    for (a, b, out) in cases {
+      assert_eq!(a.nand(b), out, "{} nand {}", a, b);
+    }
+
+    for a in [false, true] {
+      for b in [false, true] {
+        assert_eq!(Logic::from(a).nand(Logic::from(b)), Logic::from(!(a && b)));
+        assert_eq!(Logic::from(a).or(Logic::from(b)), Logic::from(a || b));
+      }
+    }
+  }
+}
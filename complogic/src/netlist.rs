@@ -0,0 +1,168 @@
+use std::fmt;
+
+use crate::{Op, Ops};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetlistError {
+  /// A line starts with something other than an op
+  UnknownOp { line: usize, op: String },
+
+  /// A line has the wrong operands for its op
+  Malformed { line: usize, text: String },
+}
+
+impl fmt::Display for NetlistError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      NetlistError::UnknownOp { line, op } => {
+        write!(f, "line {}: unknown op {}", line, op)
+      }
+      NetlistError::Malformed { line, text } => {
+        write!(f, "line {}: malformed op \"{}\"", line, text)
+      }
+    }
+  }
+}
+
+impl std::error::Error for NetlistError {}
+
+/// Writes ops as a netlist, with one op per line
+///
+/// Nands are written as `NAND r0 r1 -> r2` and Sets as `SET r0 false`. Buf
+/// and Inv ops from folding are written as `BUF r0 -> r1` and `INV r0 -> r1`,
+/// and the And and Or ops of primitive lowering like Nands.
+pub fn write_netlist(ops: &[Op]) -> String {
+  ops.iter().map(|op| format!("{}\n", op)).collect()
+}
+
+/// Reads ops from a netlist written by [`write_netlist`]
+///
+/// Blank lines and lines starting with `#` are skipped, so netlists can be
+/// edited by hand.
+pub fn read_netlist(s: &str) -> Result<Ops, NetlistError> {
+  let mut ops: Ops = vec![];
+
+  for (i, text) in s.lines().enumerate() {
+    let text = text.trim();
+    if text.is_empty() || text.starts_with('#') {
+      continue;
+    }
+
+    let line = i + 1;
+    let malformed = || NetlistError::Malformed {
+      line,
+      text: text.to_string(),
+    };
+    let reg = |token: &str| {
+      token
+        .strip_prefix('r')
+        .and_then(|index| index.parse::<usize>().ok())
+        .ok_or_else(malformed)
+    };
+
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let op = match tokens.as_slice() {
+      ["NAND", a, b, "->", out] => Op::Nand(reg(a)?, reg(b)?, reg(out)?),
+      ["SET", r, val] => {
+        Op::Set(reg(r)?, val.parse::<bool>().map_err(|_| malformed())?)
+      }
+      ["BUF", a, "->", out] => Op::Buf(reg(a)?, reg(out)?),
+      ["INV", a, "->", out] => Op::Inv(reg(a)?, reg(out)?),
+      ["AND", a, b, "->", out] => Op::And(reg(a)?, reg(b)?, reg(out)?),
+      ["OR", a, b, "->", out] => Op::Or(reg(a)?, reg(b)?, reg(out)?),
+      ["NAND" | "SET" | "BUF" | "INV" | "AND" | "OR", ..] => {
+        return Err(malformed())
+      }
+      [op, ..] => {
+        return Err(NetlistError::UnknownOp {
+          line,
+          op: op.to_string(),
+        })
+      }
+      [] => unreachable!("blank lines are skipped"),
+    };
+
+    ops.push(op);
+  }
+
+  Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Compiler, FullAdder, Gate};
+
+  #[test]
+  /// Test that the ops of a compiled circuit read back the same
+  fn round_trip() {
+    let mut compiler = Compiler::new(3);
+    let [s, cout] = [compiler.alloc(), compiler.alloc()];
+    let simulation = compiler
+      .compile(vec![&Gate::from(FullAdder {
+        a: 0,
+        b: 1,
+        cin: 2,
+        s,
+        cout,
+      })])
+      .unwrap();
+
+    let mut ops = simulation.ops.clone();
+    ops.extend([
+      Op::Buf(0, 1),
+      Op::Inv(1, 2),
+      Op::And(0, 1, 3),
+      Op::Or(2, 3, 4),
+      Op::Set(4, true),
+    ]);
+
+    let netlist = write_netlist(&ops);
+    assert!(netlist.starts_with("NAND "));
+    assert_eq!(read_netlist(&netlist).unwrap(), ops);
+  }
+
+  #[test]
+  /// Test that comments are skipped and bad lines report their line number
+  fn parse_errors() {
+    let netlist = "# inputs\nSET r0 true\n\nNAND r0 r0 -> r1\n";
+    assert_eq!(
+      read_netlist(netlist).unwrap(),
+      vec![Op::Set(0, true), Op::Nand(0, 0, 1)]
+    );
+
+    let err = read_netlist("SET r0 false\nNAND r0 -> r1\n").unwrap_err();
+    assert_eq!(
+      err,
+      NetlistError::Malformed {
+        line: 2,
+        text: "NAND r0 -> r1".to_string(),
+      }
+    );
+    assert_eq!(err.to_string(), "line 2: malformed op \"NAND r0 -> r1\"");
+
+    assert_eq!(
+      read_netlist("SET x0 false").unwrap_err(),
+      NetlistError::Malformed {
+        line: 1,
+        text: "SET x0 false".to_string(),
+      }
+    );
+    for text in ["AND r0 -> r2", "OR r0 r1 r2"] {
+      assert_eq!(
+        read_netlist(text).unwrap_err(),
+        NetlistError::Malformed {
+          line: 1,
+          text: text.to_string(),
+        }
+      );
+    }
+    assert_eq!(
+      read_netlist("\nXOR r0 r1 -> r2").unwrap_err(),
+      NetlistError::UnknownOp {
+        line: 2,
+        op: "XOR".to_string(),
+      }
+    );
+  }
+}
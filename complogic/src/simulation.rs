@@ -1,6 +1,31 @@
+use std::{
+  cmp::Reverse,
+  collections::{BinaryHeap, HashMap, HashSet},
+  fmt, fs,
+  path::Path,
+};
+
+use petgraph::{
+  dot::{Config, Dot},
+  graph::DiGraph,
+  stable_graph::NodeIndex,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::Op;
+use crate::{
+  from_bits, op_inputs, op_output, to_bits, FileError, Logic, Op, Ops,
+};
+
+/// The most runs a truth table row is given to settle
+const MAX_RUNS: usize = 100;
+
+/// The fewest ops a layer needs to be run in parallel, below which
+/// splitting it up costs more than it saves
+#[cfg(feature = "rayon")]
+const MIN_PARALLEL_LAYER: usize = 256;
+
+/// A function that runs ops on the registers, given the immediates
+pub type RunFn = Box<dyn Fn(&mut [bool], &[bool])>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Simulation {
@@ -9,35 +34,1104 @@ pub struct Simulation {
 
   /// Stores the values of the registers
   pub registers: Vec<bool>,
+
+  /// Stores the propagation delay (in ps) of each op, ops without a delay
+  /// take 1ps
+  ///
+  /// The compiler leaves this empty, so the delays need to be set by hand
+  /// (one for each of [`Simulation::ops`]) before a timed run.
+  #[serde(default)]
+  pub delays: Vec<u32>,
+
+  /// The number of immediate values the simulation expects
+  #[serde(default)]
+  pub immediate_count: usize,
+
+  /// The index of each op that reads from each register, which is rebuilt
+  /// by [`Simulation::index_readers`] whenever the ops change
+  #[serde(skip)]
+  pub readers: Vec<Vec<usize>>,
+
+  /// The registers before the last run, which is reused between runs so
+  /// checking for changes doesn't allocate
+  #[serde(skip)]
+  pub previous: Vec<bool>,
+
+  /// Registers stuck at a value, whatever the op driving them writes
+  #[serde(default)]
+  pub faults: HashMap<usize, bool>,
+
+  /// Immediates held at a value on every run, whatever is passed to the run
+  #[serde(default)]
+  pub persistent: HashMap<usize, bool>,
+
+  /// The index of the layer each op was scheduled in (see
+  /// [`crate::Compiler::layers`])
+  #[serde(default)]
+  pub op_layers: Vec<usize>,
+
+  /// Whether each register has been seen low and high since coverage was
+  /// last reset, which is only tracked once it has been reset
+  #[serde(skip)]
+  pub coverage: Option<Vec<(bool, bool)>>,
+
+  /// The immediates passed to each run since the log was enabled, which is
+  /// only recorded once it has been enabled
+  #[serde(skip)]
+  pub log: Option<Vec<Vec<bool>>>,
+
+  /// The registers of the three-valued runs, which are only simulated once
+  /// they've been enabled
+  #[serde(skip)]
+  pub logic: Option<Vec<Logic>>,
+
+  /// The outputs of the compiled gates in register order, leaving out the
+  /// registers used inside of gates
+  #[serde(default)]
+  pub visible: Vec<usize>,
+
+  /// The registers watched by probes and the name of each probe, in the
+  /// order they were added
+  #[serde(default)]
+  pub watched: Vec<(usize, String)>,
+
+  /// Names of registers, such as the inputs and outputs, which are given by
+  /// the compiler's labels (see [`crate::Compiler::label`])
+  #[serde(default)]
+  pub labels: HashMap<usize, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+  /// The number of immediates doesn't match the immediate count
+  ImmediateCount { expected: usize, found: usize },
+
+  /// There's no immediate labeled with the name
+  UnknownInput(String),
+
+  /// The register isn't an immediate
+  NotAnImmediate { reg: usize, immediate_count: usize },
+}
+
+impl fmt::Display for RunError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RunError::ImmediateCount { expected, found } => {
+        write!(f, "expected {} immediates, found {}", expected, found)
+      }
+      RunError::UnknownInput(name) => write!(f, "unknown input {}", name),
+      RunError::NotAnImmediate {
+        reg,
+        immediate_count,
+      } => write!(
+        f,
+        "register {} isn't one of the {} immediates",
+        reg, immediate_count
+      ),
+    }
+  }
+}
+
+impl std::error::Error for RunError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+  /// The op at the index uses a register outside of the registers
+  RegisterOutOfRange {
+    op: usize,
+    reg: usize,
+    register_count: usize,
+  },
+
+  /// More than one op writes to the register
+  MultipleDrivers { reg: usize },
+}
+
+impl fmt::Display for BuildError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BuildError::RegisterOutOfRange {
+        op,
+        reg,
+        register_count,
+      } => write!(
+        f,
+        "op {} uses register {}, out of range of the {} registers",
+        op, reg, register_count
+      ),
+      BuildError::MultipleDrivers { reg } => {
+        write!(f, "register {} has more than one driver", reg)
+      }
+    }
+  }
 }
 
+impl std::error::Error for BuildError {}
+
 impl Simulation {
+  /// Builds a simulation that runs already scheduled ops, such as ones made
+  /// by another tool
+  ///
+  /// Every register starts low. There are no immediates, so Sets read their
+  /// value from the immediates of each run by their register instead.
+  pub fn from_ops(ops: Ops, register_count: usize) -> Result<Self, BuildError> {
+    let mut driven: HashSet<usize> = HashSet::new();
+    for (i, op) in ops.iter().enumerate() {
+      let out = op_output(*op);
+      if let Some(reg) = op_inputs(*op)
+        .into_iter()
+        .chain([out])
+        .find(|reg| *reg >= register_count)
+      {
+        return Err(BuildError::RegisterOutOfRange {
+          op: i,
+          reg,
+          register_count,
+        });
+      }
+
+      if !driven.insert(out) {
+        return Err(BuildError::MultipleDrivers { reg: out });
+      }
+    }
+
+    let mut simulation = Simulation {
+      registers: vec![false; register_count],
+      ops,
+      ..Default::default()
+    };
+    simulation.index_readers();
+
+    Ok(simulation)
+  }
+
   /// Runs the simulation
+  ///
+  /// The immediates are set first, ones that aren't given are low.
   pub fn run(&mut self, immediates: &[bool]) {
+    if let Some(log) = self.log.as_mut() {
+      log.push(immediates.to_vec());
+    }
+
+    for id in 0..self.immediate_count {
+      self.registers[id] = self.immediate(id, immediates, false);
+    }
+
+    for i in 0..self.ops.len() {
+      self.run_op(i, immediates);
+    }
+
+    self.record_coverage();
+  }
+
+  /// Runs the op at `pc` like a run would, and gets the index of the next op
+  ///
+  /// Stepping from the first op sets the immediates first, and the index
+  /// wraps back to the first op after the last one, so stepping through
+  /// every op from 0 is the same as a [`Simulation::run`].
+  pub fn step(&mut self, pc: usize, immediates: &[bool]) -> usize {
+    if pc == 0 {
+      for id in 0..self.immediate_count {
+        self.registers[id] = self.immediate(id, immediates, false);
+      }
+    }
+
+    if pc < self.ops.len() {
+      self.run_op(pc, immediates);
+    }
+
+    if pc + 1 >= self.ops.len() {
+      self.record_coverage();
+      0
+    } else {
+      pc + 1
+    }
+  }
+
+  /// Runs the simulation with the immediates given by their labels
+  ///
+  /// Immediates that aren't given are low. Nothing is run if a name isn't
+  /// the label of an immediate.
+  pub fn run_named(
+    &mut self,
+    inputs: &HashMap<String, bool>,
+  ) -> Result<(), RunError> {
+    let mut immediates = vec![false; self.immediate_count];
+    for (name, value) in inputs.iter() {
+      let reg = self
+        .labels
+        .iter()
+        .find(|(reg, label)| **reg < self.immediate_count && *label == name)
+        .map(|(reg, _)| *reg);
+      match reg {
+        Some(reg) => immediates[reg] = *value,
+        None => return Err(RunError::UnknownInput(name.clone())),
+      }
+    }
+
+    self.run(&immediates);
+    Ok(())
+  }
+
+  /// Runs the simulation like [`Simulation::run`], running the ops of each
+  /// layer in parallel
+  ///
+  /// The layers come from [`Simulation::op_layers`]. Layers that are small,
+  /// or whose ops read each other (like the loop of a latch), run in order,
+  /// so the registers end up the same as a serial run. Simulations without
+  /// layers, such as ones built from ops, run serially.
+  #[cfg(feature = "rayon")]
+  pub fn run_parallel(&mut self, immediates: &[bool]) {
+    use rayon::prelude::*;
+
+    if self.op_layers.len() != self.ops.len() {
+      return self.run(immediates);
+    }
+
+    if let Some(log) = self.log.as_mut() {
+      log.push(immediates.to_vec());
+    }
+
+    for id in 0..self.immediate_count {
+      self.registers[id] = self.immediate(id, immediates, false);
+    }
+
+    let mut start = 0;
+    while start < self.ops.len() {
+      let layer = self.op_layers[start];
+      let end = start
+        + self.op_layers[start..]
+          .iter()
+          .take_while(|op_layer| **op_layer == layer)
+          .count();
+
+      let ops = &self.ops[start..end];
+      let outputs: HashSet<usize> =
+        ops.iter().map(|op| op_output(*op)).collect();
+      let independent = ops
+        .iter()
+        .all(|op| op_inputs(*op).iter().all(|reg| !outputs.contains(reg)));
+
+      if ops.len() < MIN_PARALLEL_LAYER || !independent {
+        for i in start..end {
+          self.run_op(i, immediates);
+        }
+      } else {
+        let values: Vec<bool> = (start..end)
+          .into_par_iter()
+          .map(|i| self.op_value(i, immediates))
+          .collect();
+        for (i, value) in (start..end).zip(values) {
+          self.registers[op_output(self.ops[i])] = value;
+        }
+      }
+
+      start = end;
+    }
+
+    self.record_coverage();
+  }
+
+  /// Runs a single op
+  fn run_op(&mut self, i: usize, immediates: &[bool]) {
+    match self.ops[i] {
+      Op::Nand(a, b, out) => {
+        let a = self.registers[a];
+        let b = self.registers[b];
+
+        self.registers[out] = !(a && b);
+      }
+      Op::Set(id, val) => {
+        self.registers[id] = self.immediate(id, immediates, val);
+      }
+      Op::Buf(a, out) => {
+        self.registers[out] = self.registers[a];
+      }
+      Op::Inv(a, out) => {
+        self.registers[out] = !self.registers[a];
+      }
+      Op::And(a, b, out) => {
+        self.registers[out] = self.registers[a] && self.registers[b];
+      }
+      Op::Or(a, b, out) => {
+        self.registers[out] = self.registers[a] || self.registers[b];
+      }
+    }
+
+    if !self.faults.is_empty() {
+      let out = op_output(self.ops[i]);
+      if let Some(val) = self.faults.get(&out) {
+        self.registers[out] = *val;
+      }
+    }
+  }
+
+  /// Gets the value a single op writes to its output like
+  /// [`Simulation::run_op`], without writing it
+  fn op_value(&self, i: usize, immediates: &[bool]) -> bool {
+    let value = match self.ops[i] {
+      Op::Nand(a, b, _) => !(self.registers[a] && self.registers[b]),
+      Op::Set(id, val) => self.immediate(id, immediates, val),
+      Op::Buf(a, _) => self.registers[a],
+      Op::Inv(a, _) => !self.registers[a],
+      Op::And(a, b, _) => self.registers[a] && self.registers[b],
+      Op::Or(a, b, _) => self.registers[a] || self.registers[b],
+    };
+
+    if self.faults.is_empty() {
+      return value;
+    }
+    let out = op_output(self.ops[i]);
+    self.faults.get(&out).copied().unwrap_or(value)
+  }
+
+  /// Gets the value a Set writes to an immediate, from the faults, then the
+  /// persistent inputs, then the immediates of the run, then the compiled
+  /// default
+  fn immediate(&self, id: usize, immediates: &[bool], val: bool) -> bool {
+    match self.faults.get(&id).or_else(|| self.persistent.get(&id)) {
+      Some(val) => *val,
+      None => immediates.get(id).copied().unwrap_or(val),
+    }
+  }
+
+  /// Holds an immediate at a value on every run until it's cleared, so it
+  /// doesn't need to be passed again
+  ///
+  /// The value overrides whatever the run is given for the immediate.
+  pub fn set_persistent_input(&mut self, reg: usize, value: bool) {
+    self.persistent.insert(reg, value);
+  }
+
+  /// Stops holding every persistent input, which takes effect on the next run
+  pub fn clear_persistent_inputs(&mut self) {
+    self.persistent.clear();
+  }
+
+  /// Builds a function that runs the ops like [`Simulation::run`], given the
+  /// registers and the immediates
+  ///
+  /// Each op is turned into a closure over its registers up front, so running
+  /// doesn't need to match on the ops every pass. Persistent inputs and
+  /// faults are captured when the function is built.
+  pub fn compile_fn(&self) -> RunFn {
+    let persistent: Vec<Option<bool>> = (0..self.immediate_count)
+      .map(|id| {
+        self
+          .faults
+          .get(&id)
+          .or_else(|| self.persistent.get(&id))
+          .copied()
+      })
+      .collect();
+    let set_immediates: RunFn = Box::new(move |registers, immediates| {
+      for (id, val) in persistent.iter().enumerate() {
+        registers[id] =
+          val.unwrap_or_else(|| immediates.get(id).copied().unwrap_or(false));
+      }
+    });
+
+    let ops: Vec<RunFn> = std::iter::once(set_immediates)
+      .chain(self.ops.iter().map(|op| -> RunFn {
+        if let Some(val) = self.faults.get(&op_output(*op)).copied() {
+          let out = op_output(*op);
+          return Box::new(move |registers, _| {
+            registers[out] = val;
+          });
+        }
+
+        match *op {
+          Op::Nand(a, b, out) => Box::new(move |registers, _| {
+            registers[out] = !(registers[a] && registers[b]);
+          }),
+          Op::Set(id, val) => match self.persistent.get(&id).copied() {
+            Some(val) => Box::new(move |registers, _| {
+              registers[id] = val;
+            }),
+            None => Box::new(move |registers, immediates| {
+              registers[id] = immediates.get(id).copied().unwrap_or(val);
+            }),
+          },
+          Op::Buf(a, out) => Box::new(move |registers, _| {
+            registers[out] = registers[a];
+          }),
+          Op::Inv(a, out) => Box::new(move |registers, _| {
+            registers[out] = !registers[a];
+          }),
+          Op::And(a, b, out) => Box::new(move |registers, _| {
+            registers[out] = registers[a] && registers[b];
+          }),
+          Op::Or(a, b, out) => Box::new(move |registers, _| {
+            registers[out] = registers[a] || registers[b];
+          }),
+        }
+      }))
+      .collect();
+
+    Box::new(move |registers, immediates| {
+      for op in ops.iter() {
+        op(registers, immediates);
+      }
+    })
+  }
+
+  /// Rebuilds the index of the ops that read from each register
+  pub fn index_readers(&mut self) {
+    self.readers = vec![vec![]; self.registers.len()];
+    for (i, op) in self.ops.iter().enumerate() {
+      for input in op_inputs(*op) {
+        self.readers[input].push(i);
+      }
+    }
+  }
+
+  /// Sets the changed immediates and only runs the ops downstream of them
+  ///
+  /// This matches a full [`Simulation::run`] with the new immediates as long
+  /// as the simulation was settled before the change. The other immediates
+  /// keep their values, and the run is logged and held by the persistent
+  /// inputs and faults like a full run. Nothing is run if a changed register
+  /// isn't an immediate.
+  pub fn run_incremental(
+    &mut self,
+    changed: &[(usize, bool)],
+  ) -> Result<(), RunError> {
+    if let Some((reg, _)) =
+      changed.iter().find(|(reg, _)| *reg >= self.immediate_count)
+    {
+      return Err(RunError::NotAnImmediate {
+        reg: *reg,
+        immediate_count: self.immediate_count,
+      });
+    }
+
+    if self.readers.len() != self.registers.len() {
+      self.index_readers();
+    }
+
+    let mut immediates = self.registers[..self.immediate_count].to_vec();
+    for (reg, val) in changed.iter().copied() {
+      immediates[reg] = val;
+    }
+    if let Some(log) = self.log.as_mut() {
+      log.push(immediates.clone());
+    }
+
+    let mut affected = vec![];
+    let mut seen: HashSet<usize> = HashSet::new();
+    let mut queue: Vec<usize> = vec![];
+    for (reg, _) in changed.iter().copied() {
+      self.registers[reg] = self.immediate(reg, &immediates, false);
+      queue.push(reg);
+    }
+
+    while let Some(reg) = queue.pop() {
+      for i in self.readers[reg].iter().copied() {
+        if seen.insert(i) {
+          affected.push(i);
+          queue.push(op_output(self.ops[i]));
+        }
+      }
+    }
+
+    // Ops are run in their scheduled order, like a full run
+    affected.sort_unstable();
+    for i in affected {
+      self.run_op(i, &immediates);
+    }
+
+    self.record_coverage();
+    Ok(())
+  }
+
+  /// Starts simulating with three values, where every register is unknown
+  /// until it's driven to a known value
+  ///
+  /// The three-valued registers are kept apart from [`Simulation::registers`]
+  /// and only change on [`Simulation::run_logic`], so runs with two values
+  /// stay as fast as before.
+  pub fn enable_three_valued(&mut self) {
+    self.logic = Some(vec![Logic::X; self.registers.len()]);
+  }
+
+  /// Runs the ops with three values, enabling three-valued simulation if it
+  /// isn't already
+  ///
+  /// Immediates that aren't given are unknown. A low input still decides the
+  /// output of a Nand or And, as a high input does for an Or, so only the
+  /// outputs that depend on an unknown value are unknown.
+  pub fn run_logic(&mut self, immediates: &[Logic]) {
+    let mut logic = self
+      .logic
+      .take()
+      .unwrap_or_else(|| vec![Logic::X; self.registers.len()]);
+    logic.resize(self.registers.len(), Logic::X);
+
+    let immediate = |id: usize, val: Logic| match self
+      .faults
+      .get(&id)
+      .or_else(|| self.persistent.get(&id))
+    {
+      Some(val) => Logic::from(*val),
+      None => immediates.get(id).copied().unwrap_or(val),
+    };
+    for (id, val) in logic.iter_mut().enumerate().take(self.immediate_count) {
+      *val = immediate(id, Logic::X);
+    }
+
     for op in self.ops.iter() {
-      match *op {
-        Op::Nand(a, b, out) => {
-          let a = self.registers[a];
-          let b = self.registers[b];
+      let (out, val) = match *op {
+        Op::Nand(a, b, out) => (out, logic[a].nand(logic[b])),
+        Op::Set(id, val) => (id, immediate(id, Logic::from(val))),
+        Op::Buf(a, out) => (out, logic[a]),
+        Op::Inv(a, out) => (out, !logic[a]),
+        Op::And(a, b, out) => (out, logic[a].and(logic[b])),
+        Op::Or(a, b, out) => (out, logic[a].or(logic[b])),
+      };
+
+      logic[out] = match self.faults.get(&out) {
+        Some(fault) => Logic::from(*fault),
+        None => val,
+      };
+    }
+
+    self.logic = Some(logic);
+  }
+
+  /// Gets the three-valued value of a register, which is unknown unless
+  /// three-valued simulation is enabled
+  pub fn logic_value(&self, reg: usize) -> Logic {
+    self
+      .logic
+      .as_ref()
+      .and_then(|logic| logic.get(reg).copied())
+      .unwrap_or_default()
+  }
+
+  /// Replaces Nand ops with constant inputs with cheaper ops
+  ///
+  /// Registers other than the immediates that are never written to are
+  /// constant. Sets aren't, since they can be given a value by the run. A
+  /// Nand with a low input always outputs high and a Nand with a high input
+  /// (or the same input twice) inverts its other input, while inverting an
+  /// inverted register copies the original register. Ands and Ors with
+  /// constant inputs likewise become copies. Ops whose output is constant are
+  /// removed, leaving the value in their register, which is constant from
+  /// then on. The simulation runs the same after folding.
+  pub fn fold_constants(&mut self) {
+    // The index of every op that writes to each register
+    let mut drivers: Vec<Vec<usize>> = vec![vec![]; self.registers.len()];
+    for (i, op) in self.ops.iter().enumerate() {
+      drivers[op_output(*op)].push(i);
+    }
+
+    let mut constants: Vec<Option<bool>> = self
+      .registers
+      .iter()
+      .zip(drivers.iter())
+      .enumerate()
+      .map(|(reg, (val, drivers))| {
+        (reg >= self.immediate_count && drivers.is_empty()).then_some(*val)
+      })
+      .collect();
 
-          self.registers[out] = !(a && b);
+    let mut removed: HashSet<usize> = HashSet::new();
+    for i in 0..self.ops.len() {
+      let op = match self.ops[i] {
+        Op::Nand(a, b, out) => match (constants[a], constants[b]) {
+          (Some(a), Some(b)) => Op::Set(out, !(a && b)),
+          (Some(false), _) | (_, Some(false)) => Op::Set(out, true),
+          (Some(true), _) => Op::Inv(b, out),
+          (_, Some(true)) => Op::Inv(a, out),
+          _ if a == b => Op::Inv(a, out),
+          _ => Op::Nand(a, b, out),
+        },
+        Op::Buf(a, out) => {
+          constants[a].map_or(Op::Buf(a, out), |a| Op::Set(out, a))
         }
-        Op::Set(id, val) => {
-          self.registers[id] = immediates.get(id).copied().unwrap_or(val);
+        Op::Inv(a, out) => {
+          constants[a].map_or(Op::Inv(a, out), |a| Op::Set(out, !a))
         }
+        Op::And(a, b, out) => match (constants[a], constants[b]) {
+          (Some(a), Some(b)) => Op::Set(out, a && b),
+          (Some(false), _) | (_, Some(false)) => Op::Set(out, false),
+          (Some(true), _) => Op::Buf(b, out),
+          (_, Some(true)) => Op::Buf(a, out),
+          _ if a == b => Op::Buf(a, out),
+          _ => Op::And(a, b, out),
+        },
+        Op::Or(a, b, out) => match (constants[a], constants[b]) {
+          (Some(a), Some(b)) => Op::Set(out, a || b),
+          (Some(true), _) | (_, Some(true)) => Op::Set(out, true),
+          (Some(false), _) => Op::Buf(b, out),
+          (_, Some(false)) => Op::Buf(a, out),
+          _ if a == b => Op::Buf(a, out),
+          _ => Op::Or(a, b, out),
+        },
+        op => op,
+      };
+
+      // The input of an Inv that wrote the register hasn't changed since if
+      // it's only written to before that Inv
+      let op = match op {
+        Op::Inv(b, out) => match drivers[b][..] {
+          [driver] if driver < i => match self.ops[driver] {
+            Op::Inv(a, _) if drivers[a].iter().all(|d| *d < driver) => {
+              Op::Buf(a, out)
+            }
+            _ => op,
+          },
+          _ => op,
+        },
+        op => op,
+      };
+
+      if let (Op::Set(out, val), false) =
+        (op, matches!(self.ops[i], Op::Set(..)))
+      {
+        // Registers with another driver keep the op they had
+        if out >= self.immediate_count && drivers[out].len() == 1 {
+          constants[out] = Some(val);
+          self.registers[out] = val;
+          removed.insert(i);
+        }
+        continue;
       }
+
+      self.ops[i] = op;
     }
+
+    // The delays and layers are kept in line with the ops that are left
+    let mut ops = 0..;
+    self.ops.retain(|_| !removed.contains(&ops.next().unwrap()));
+    let mut delays = 0..;
+    self
+      .delays
+      .retain(|_| !removed.contains(&delays.next().unwrap()));
+    let mut layers = 0..;
+    self
+      .op_layers
+      .retain(|_| !removed.contains(&layers.next().unwrap()));
+
+    self.index_readers();
+  }
+
+  /// Runs the simulation and returns the values of `outputs`, in order
+  pub fn run_and_read(
+    &mut self,
+    immediates: &[bool],
+    outputs: &[usize],
+  ) -> Vec<bool> {
+    self.run(immediates);
+    outputs.iter().map(|reg| self.registers[*reg]).collect()
+  }
+
+  /// Runs the simulation and returns whether any register changed
+  ///
+  /// The registers are copied into a buffer kept on the simulation, which
+  /// only allocates the first time or when the registers grow.
+  pub fn run_report(&mut self, immediates: &[bool]) -> bool {
+    let mut previous = std::mem::take(&mut self.previous);
+    previous.clone_from(&self.registers);
+    self.run(immediates);
+
+    let changed = self.registers != previous;
+    self.previous = previous;
+    changed
+  }
+
+  /// Runs the simulation until no register changes, up to `max_runs` times,
+  /// and returns whether it settled
+  pub fn run_until_stable(
+    &mut self,
+    immediates: &[bool],
+    max_runs: usize,
+  ) -> bool {
+    (0..max_runs).any(|_| !self.run_report(immediates))
+  }
+
+  /// Runs the simulation, erroring instead of falling back to the compiled
+  /// defaults when the immediates don't match the immediate count
+  pub fn run_strict(&mut self, immediates: &[bool]) -> Result<(), RunError> {
+    if immediates.len() != self.immediate_count {
+      return Err(RunError::ImmediateCount {
+        expected: self.immediate_count,
+        found: immediates.len(),
+      });
+    }
+
+    self.run(immediates);
+    Ok(())
+  }
+
+  /// Sweeps every combination of the immediates in `inputs` and returns the
+  /// values of `outputs` for each, as `(inputs, outputs)` rows
+  ///
+  /// Combinations count up with the first input as the most significant bit,
+  /// every other immediate is held low. The simulation is cloned for the
+  /// sweep, so its registers are left as they are.
+  pub fn truth_table(
+    &self,
+    inputs: &[usize],
+    outputs: &[usize],
+  ) -> Vec<(Vec<bool>, Vec<bool>)> {
+    let mut simulation = self.clone();
+    let len = inputs
+      .iter()
+      .map(|input| input + 1)
+      .max()
+      .unwrap_or(0)
+      .max(self.immediate_count);
+
+    (0..1usize << inputs.len())
+      .map(|combination| {
+        let values = to_bits(combination, inputs.len());
+
+        let mut immediates = vec![false; len];
+        for (input, value) in inputs.iter().zip(values.iter()) {
+          immediates[*input] = *value;
+        }
+        simulation.run_until_stable(&immediates, MAX_RUNS);
+
+        let outputs = outputs
+          .iter()
+          .map(|out| simulation.registers[*out])
+          .collect();
+        (values, outputs)
+      })
+      .collect()
+  }
+
+  /// Runs the simulation with propagation delays and returns every register
+  /// transition as `(time, register, value)`, ordered by time
+  ///
+  /// The registers are expected to be settled (e.g. by [`Simulation::run`])
+  /// before the immediates change. Transitions after `max_time` are dropped,
+  /// which also stops circuits that never settle. Faulted registers keep
+  /// their value like they do in a run.
+  pub fn run_timed(
+    &mut self,
+    immediates: &[bool],
+    max_time: u32,
+  ) -> Vec<(u32, usize, bool)> {
+    if self.readers.len() != self.registers.len() {
+      self.index_readers();
+    }
+    let delay = |i: usize| self.delays.get(i).copied().unwrap_or(1);
+
+    // Pending assignments as (time, order, register, value), the order keeps
+    // assignments at the same time in the order they were scheduled
+    let mut queue: BinaryHeap<Reverse<(u32, usize, usize, bool)>> =
+      BinaryHeap::new();
+    let mut order = 0;
+
+    // Immediates without a Set are set after the default delay of an op
+    for id in 0..self.immediate_count {
+      if !self
+        .ops
+        .iter()
+        .any(|op| matches!(op, Op::Set(reg, _) if *reg == id))
+      {
+        let val = self.immediate(id, immediates, false);
+        queue.push(Reverse((1, order, id, val)));
+        order += 1;
+      }
+    }
+    for (i, op) in self.ops.iter().enumerate() {
+      if let Op::Set(id, _) = *op {
+        let val = self.op_value(i, immediates);
+        queue.push(Reverse((delay(i), order, id, val)));
+        order += 1;
+      }
+    }
+
+    let mut events = vec![];
+    while let Some(Reverse((time, _, reg, val))) = queue.pop() {
+      if time > max_time {
+        break;
+      }
+
+      if self.registers[reg] == val {
+        continue;
+      }
+
+      self.registers[reg] = val;
+      events.push((time, reg, val));
+
+      for i in self.readers[reg].iter().copied() {
+        if matches!(self.ops[i], Op::Set(..)) {
+          continue;
+        }
+
+        let out = op_output(self.ops[i]);
+        let val = self.op_value(i, immediates);
+        queue.push(Reverse((time + delay(i), order, out, val)));
+        order += 1;
+      }
+    }
+
+    events
+  }
+
+  /// Runs the simulation with a delay of one step for every op, and gets the
+  /// registers after each step
+  ///
+  /// The immediates are set first, then each step updates every op at once
+  /// from the registers of the step before, so a change moves one op further
+  /// along each path per step. The run is logged and counts toward the
+  /// coverage like [`Simulation::run`].
+  pub fn run_unit_delay(
+    &mut self,
+    immediates: &[bool],
+    steps: usize,
+  ) -> Vec<Vec<bool>> {
+    if let Some(log) = self.log.as_mut() {
+      log.push(immediates.to_vec());
+    }
+
+    for id in 0..self.immediate_count {
+      self.registers[id] = self.immediate(id, immediates, false);
+    }
+
+    let mut snapshots = Vec::with_capacity(steps);
+    for _ in 0..steps {
+      let values: Vec<bool> = (0..self.ops.len())
+        .map(|i| self.op_value(i, immediates))
+        .collect();
+      for (op, value) in self.ops.iter().zip(values) {
+        self.registers[op_output(*op)] = value;
+      }
+      snapshots.push(self.registers.clone());
+    }
+
+    self.record_coverage();
+    snapshots
+  }
+
+  /// Exports the scheduled ops as a DOT graph, with an edge from each op to
+  /// the ops that read the register it writes to
+  pub fn to_dot(&self) -> String {
+    let mut graph = DiGraph::<String, &str, usize>::default();
+
+    // The op that writes to each register
+    let mut writers: HashMap<usize, NodeIndex<usize>> = HashMap::new();
+    for op in self.ops.iter() {
+      let (label, out) = match *op {
+        Op::Nand(a, b, out) => {
+          (format!("r{} = Nand(r{}, r{})", out, a, b), out)
+        }
+        Op::Set(reg, val) => (format!("r{} = Set({})", reg, val), reg),
+        Op::Buf(a, out) => (format!("r{} = Buf(r{})", out, a), out),
+        Op::Inv(a, out) => (format!("r{} = Inv(r{})", out, a), out),
+        Op::And(a, b, out) => (format!("r{} = And(r{}, r{})", out, a, b), out),
+        Op::Or(a, b, out) => (format!("r{} = Or(r{}, r{})", out, a, b), out),
+      };
+      writers.insert(out, graph.add_node(label));
+    }
+
+    for (i, op) in self.ops.iter().enumerate() {
+      for input in op_inputs(*op) {
+        if let Some(writer) = writers.get(&input) {
+          graph.add_edge(*writer, NodeIndex::new(i), "");
+        }
+      }
+    }
+
+    format!("{}", Dot::with_config(&graph, &[Config::EdgeNoLabel]))
+  }
+
+  /// Gets the number of ops run by the simulation
+  pub fn op_count(&self) -> usize {
+    self.ops.len()
+  }
+
+  /// Gets the number of registers in the simulation
+  pub fn register_count(&self) -> usize {
+    self.registers.len()
+  }
+
+  /// Gets the number of Nand ops run by the simulation
+  pub fn active_nand_count(&self) -> usize {
+    self
+      .ops
+      .iter()
+      .filter(|op| matches!(op, Op::Nand(..)))
+      .count()
   }
 
   /// Gets a register value
   pub fn register(&self, id: usize) -> bool {
     self.registers[id]
   }
+
+  /// Reads the registers as a number, with the first register as the most
+  /// significant bit
+  pub fn read_number(&self, regs: &[usize]) -> usize {
+    let bits: Vec<bool> = regs.iter().map(|reg| self.registers[*reg]).collect();
+    from_bits(&bits)
+  }
+
+  /// Writes a number to the registers, with the first register as the most
+  /// significant bit
+  ///
+  /// Bits that don't fit in the registers are dropped.
+  pub fn write_number(&mut self, regs: &[usize], value: usize) {
+    for (reg, bit) in regs.iter().zip(to_bits(value, regs.len())) {
+      self.registers[*reg] = bit;
+    }
+  }
+
+  /// Gets the layer an op was scheduled in, if the simulation was compiled
+  pub fn layer_of(&self, op: usize) -> Option<usize> {
+    self.op_layers.get(op).copied()
+  }
+
+  /// Gets the value of every register
+  pub fn value_map(&self) -> &[bool] {
+    &self.registers
+  }
+
+  /// Gets the index and value of each output of the compiled gates
+  pub fn visible_values(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+    self.visible.iter().map(|reg| (*reg, self.registers[*reg]))
+  }
+
+  /// Watches a register under a name, replacing any probe with the same name
+  pub fn add_probe(&mut self, reg: usize, name: &str) {
+    match self.watched.iter_mut().find(|(_, probe)| probe == name) {
+      Some(probe) => probe.0 = reg,
+      None => self.watched.push((reg, name.to_string())),
+    }
+  }
+
+  /// Gets the name and current value of each probe
+  pub fn probes(&self) -> Vec<(String, bool)> {
+    self
+      .watched
+      .iter()
+      .map(|(reg, name)| (name.clone(), self.registers[*reg]))
+      .collect()
+  }
+
+  /// Starts tracking which registers toggle, forgetting what was seen before
+  pub fn reset_coverage(&mut self) {
+    self.coverage = Some(vec![(false, false); self.registers.len()]);
+  }
+
+  /// Gets each register and whether it has been seen low and high after a
+  /// run since [`Simulation::reset_coverage`]
+  ///
+  /// Registers that were never seen both ways are stuck for the runs so far,
+  /// which can point to dead logic. Nothing has been seen without a reset.
+  pub fn toggle_coverage(&self) -> Vec<(usize, bool, bool)> {
+    (0..self.registers.len())
+      .map(|reg| {
+        let (low, high) = self
+          .coverage
+          .as_ref()
+          .and_then(|coverage| coverage.get(reg).copied())
+          .unwrap_or_default();
+        (reg, low, high)
+      })
+      .collect()
+  }
+
+  /// Gets each register that had the same value after every run since
+  /// [`Simulation::reset_coverage`], with that value
+  ///
+  /// These come from the toggle coverage, so they're only as good as the
+  /// runs: a register that never changes likely points to dead logic or a
+  /// wiring mistake. Nothing is reported without a reset.
+  pub fn constant_registers(&self) -> Vec<(usize, bool)> {
+    self
+      .toggle_coverage()
+      .into_iter()
+      .filter_map(|(reg, low, high)| match (low, high) {
+        (true, false) => Some((reg, false)),
+        (false, true) => Some((reg, true)),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Marks the current value of each register as seen, if coverage is being
+  /// tracked
+  fn record_coverage(&mut self) {
+    if let Some(coverage) = self.coverage.as_mut() {
+      coverage.resize(self.registers.len(), (false, false));
+      for (seen, val) in coverage.iter_mut().zip(self.registers.iter()) {
+        if *val {
+          seen.1 = true;
+        } else {
+          seen.0 = true;
+        }
+      }
+    }
+  }
+
+  /// Starts recording the immediates of every run, forgetting what was
+  /// recorded before
+  pub fn enable_log(&mut self) {
+    self.log = Some(vec![]);
+  }
+
+  /// Gets the immediates of each run since [`Simulation::enable_log`], in
+  /// order
+  ///
+  /// Running them on a simulation in the same state reproduces the same
+  /// registers, see [`Simulation::replay`].
+  pub fn replay_log(&self) -> &[Vec<bool>] {
+    self.log.as_deref().unwrap_or_default()
+  }
+
+  /// Runs the simulation once with each of the logged immediates
+  pub fn replay(&mut self, log: &[Vec<bool>]) {
+    for immediates in log.iter() {
+      self.run(immediates);
+    }
+  }
+
+  /// Saves the replay log as JSON
+  pub fn save_log(&self, path: impl AsRef<Path>) -> Result<(), FileError> {
+    let json = serde_json::to_string(self.replay_log())
+      .map_err(|err| FileError::Json(err.to_string()))?;
+
+    fs::write(path, json).map_err(|err| FileError::Io(err.to_string()))
+  }
+
+  /// Loads a replay log saved by [`Simulation::save_log`]
+  pub fn load_log(path: impl AsRef<Path>) -> Result<Vec<Vec<bool>>, FileError> {
+    let json =
+      fs::read_to_string(path).map_err(|err| FileError::Io(err.to_string()))?;
+
+    serde_json::from_str(&json).map_err(|err| FileError::Json(err.to_string()))
+  }
+
+  /// Forces a register to a value until the faults are cleared
+  ///
+  /// The register is overwritten after the op driving it runs (or when it's
+  /// set, for an immediate), so the fault spreads to everything downstream
+  /// on the next run.
+  pub fn inject_stuck_at(&mut self, reg: usize, value: bool) {
+    self.faults.insert(reg, value);
+    self.registers[reg] = value;
+  }
+
+  /// Removes every fault, which takes effect on the next run
+  pub fn clear_faults(&mut self) {
+    self.faults.clear();
+  }
 }
 
 #[cfg(test)]
 mod tests {
-  use crate::{Op, Simulation};
+  use std::{collections::HashMap, env};
+
+  use crate::{
+    op_output, to_bits, And, BuildError, Compiler, DFlipFlop, FourBitAdder,
+    FullAdder, Gate, HalfAdder, Logic, Not, Op, OverflowMode, RSLatch,
+    RippleCarryAdder, RunError, Simulation, Xor,
+  };
 
   #[test]
   /// Test the Set operation and ensure that it works as expected
@@ -45,6 +1139,8 @@ mod tests {
     let mut simulation = Simulation {
       registers: vec![false, false, false],
       ops: vec![Op::Set(0, false), Op::Set(1, true), Op::Set(2, false)],
+      immediate_count: 2,
+      ..Default::default()
     };
 
     simulation.run(&[false, false]);
@@ -64,6 +1160,8 @@ mod tests {
     let mut simulation = Simulation {
       registers: vec![false, false, false],
       ops: vec![Op::Set(0, false), Op::Set(1, true), Op::Set(2, false)],
+      immediate_count: 2,
+      ..Default::default()
     };
 
     simulation.run(&[]);
@@ -78,6 +1176,8 @@ mod tests {
     let mut simulation = Simulation {
       registers: vec![false, false, false],
       ops: vec![Op::Set(0, false), Op::Set(1, false), Op::Nand(0, 1, 2)],
+      immediate_count: 2,
+      ..Default::default()
     };
 
     simulation.run(&[false, false]);
@@ -92,4 +1192,949 @@ mod tests {
     simulation.run(&[true, true]);
     assert!(!simulation.registers[2]);
   }
+
+  #[test]
+  /// Test that reconvergent paths of different depth produce a glitch
+  fn run_timed_glitch() {
+    // out = Nand(a, Not(a)) is always high once settled, but the Not takes
+    // longer than the direct path so out briefly drops when a rises
+    let mut simulation = Simulation {
+      registers: vec![false, false, false],
+      ops: vec![Op::Set(0, false), Op::Nand(0, 0, 1), Op::Nand(0, 1, 2)],
+      delays: vec![0, 2, 1],
+      immediate_count: 1,
+      ..Default::default()
+    };
+
+    simulation.run(&[false]);
+    assert!(simulation.registers[2]);
+
+    let events = simulation.run_timed(&[true], 100);
+    assert_eq!(
+      events,
+      vec![(0, 0, true), (1, 2, false), (2, 1, false), (3, 2, true)]
+    );
+    assert!(simulation.registers[2]);
+
+    // Nothing changes when the inputs stay the same
+    assert!(simulation.run_timed(&[true], 100).is_empty());
+  }
+
+  #[test]
+  /// Test that transitions after the max time are dropped
+  fn run_timed_max_time() {
+    let mut simulation = Simulation {
+      registers: vec![false, false],
+      ops: vec![Op::Set(0, false), Op::Nand(0, 0, 1)],
+      immediate_count: 1,
+      ..Default::default()
+    };
+
+    simulation.run(&[false]);
+    assert_eq!(simulation.run_timed(&[true], 1), vec![(1, 0, true)]);
+  }
+
+  #[test]
+  /// Test that a faulted register doesn't change in a timed run
+  fn run_timed_fault() {
+    let mut simulation = Simulation {
+      registers: vec![false, false, false],
+      ops: vec![Op::Set(0, false), Op::Nand(0, 0, 1), Op::Inv(1, 2)],
+      immediate_count: 1,
+      ..Default::default()
+    };
+
+    simulation.run(&[false]);
+    simulation.inject_stuck_at(1, true);
+    simulation.run(&[false]);
+    assert!(!simulation.registers[2]);
+
+    assert_eq!(simulation.run_timed(&[true], 100), vec![(1, 0, true)]);
+    assert!(simulation.registers[1]);
+  }
+
+  #[test]
+  /// Test the size accessors on a compiled And gate
+  fn counts() {
+    let mut compiler = Compiler::new(2);
+    let and = And {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let simulation = compiler.compile(vec![&Gate::from(and)]).unwrap();
+
+    // Two immediates, the And's output, and the output of its Nand, which
+    // are the only ops since the immediates are set without ops
+    assert_eq!(simulation.register_count(), 4);
+    assert_eq!(simulation.op_count(), 2);
+    assert_eq!(simulation.active_nand_count(), 2);
+  }
+
+  #[test]
+  /// Test that running reports whether the registers changed
+  fn run_report() {
+    let mut simulation = Simulation {
+      registers: vec![false, false, false],
+      ops: vec![Op::Set(0, false), Op::Set(1, false), Op::Nand(0, 1, 2)],
+      immediate_count: 2,
+      ..Default::default()
+    };
+
+    assert!(simulation.run_report(&[true, true]));
+    assert!(!simulation.run_report(&[true, true]));
+    assert!(simulation.run_report(&[true, false]));
+    assert!(!simulation.run_report(&[true, false]));
+  }
+
+  #[test]
+  /// Test that running until stable settles a latch but not an oscillator
+  fn run_until_stable() {
+    // A Nand loop that holds once both inputs are high
+    let mut latch = Simulation {
+      registers: vec![false, false, false],
+      ops: vec![Op::Set(0, false), Op::Nand(0, 2, 1), Op::Nand(1, 1, 2)],
+      immediate_count: 1,
+      ..Default::default()
+    };
+    assert!(latch.run_until_stable(&[true], 10));
+
+    // A Not feeding itself flips on every run
+    let mut oscillator = Simulation {
+      registers: vec![false],
+      ops: vec![Op::Nand(0, 0, 0)],
+      immediate_count: 0,
+      ..Default::default()
+    };
+    assert!(!oscillator.run_until_stable(&[], 10));
+  }
+
+  #[test]
+  /// Test that a carry stuck high shows up in the sum downstream of it
+  fn stuck_at_carry() {
+    let mut compiler = Compiler::new(4);
+    let s0 = compiler.alloc();
+    let c0 = compiler.alloc();
+    let s1 = compiler.alloc();
+    let cout = compiler.alloc();
+    let gates = [
+      Gate::from(HalfAdder {
+        a: 0,
+        b: 2,
+        s: s0,
+        c: c0,
+      }),
+      Gate::from(FullAdder {
+        a: 1,
+        b: 3,
+        cin: c0,
+        s: s1,
+        cout,
+      }),
+    ];
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+
+    // 0 + 0 gives a sum of 2 with the carry stuck high
+    simulation.inject_stuck_at(c0, true);
+    simulation.run(&[false; 4]);
+    assert!(!simulation.registers[s0]);
+    assert!(simulation.registers[c0]);
+    assert!(simulation.registers[s1]);
+    assert!(!simulation.registers[cout]);
+
+    simulation.clear_faults();
+    simulation.run(&[false; 4]);
+    assert!(!simulation.registers[c0]);
+    assert!(!simulation.registers[s1]);
+  }
+
+  #[test]
+  /// Test that an immediate stuck at a value ignores the value it's given on
+  /// every kind of run
+  fn stuck_at_immediate() {
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+    let gates = [Gate::from(And { a: 0, b: 1, out })];
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+    simulation.inject_stuck_at(0, false);
+
+    simulation.run(&[true, true]);
+    assert!(!simulation.registers[0]);
+    assert!(!simulation.registers[out]);
+
+    simulation.step(0, &[true, true]);
+    assert!(!simulation.registers[0]);
+
+    simulation.run_unit_delay(&[true, true], 4);
+    assert!(!simulation.registers[out]);
+
+    simulation.run_incremental(&[(0, true)]).unwrap();
+    assert!(!simulation.registers[out]);
+
+    simulation.run_logic(&[Logic::One, Logic::One]);
+    assert_eq!(simulation.logic_value(out), Logic::Zero);
+
+    let run = simulation.compile_fn();
+    let mut registers = simulation.registers.clone();
+    run(&mut registers, &[true, true]);
+    assert!(!registers[0]);
+    assert!(!registers[out]);
+
+    simulation.clear_faults();
+    simulation.run(&[true, true]);
+    assert!(simulation.registers[out]);
+  }
+
+  #[cfg(feature = "rayon")]
+  #[test]
+  /// Test that running the layers of a wide circuit in parallel gives the
+  /// same registers as a serial run, including a latch whose loop has to run
+  /// in order
+  fn run_parallel() {
+    let mut compiler = Compiler::new(64);
+    let mut gates: Vec<Gate> = vec![];
+    for a in 0..64 {
+      for offset in 1..=5 {
+        gates.push(Gate::from(Xor {
+          a,
+          b: (a + offset) % 64,
+          out: compiler.alloc(),
+        }));
+      }
+    }
+    gates.push(Gate::from(RSLatch {
+      s: 0,
+      r: 1,
+      q: compiler.alloc(),
+      initial: None,
+    }));
+    let mut serial = compiler.compile(gates.iter().collect()).unwrap();
+    assert!(compiler
+      .layers
+      .iter()
+      .any(|layer| layer.len() >= super::MIN_PARALLEL_LAYER));
+    let mut parallel = serial.clone();
+
+    for value in [0, usize::MAX, 0x5555_5555_5555_5555, 0x0123_4567_89ab_cdef] {
+      let immediates = to_bits(value, 64);
+      serial.run(&immediates);
+      parallel.run_parallel(&immediates);
+      assert_eq!(parallel.registers, serial.registers, "{:x}", value);
+    }
+  }
+
+  #[test]
+  /// Test that running a half adder reads back its sum and carry
+  fn run_and_read() {
+    let mut compiler = Compiler::new(2);
+    let s = compiler.alloc();
+    let c = compiler.alloc();
+    let mut simulation = compiler
+      .compile(vec![&Gate::from(HalfAdder { a: 0, b: 1, s, c })])
+      .unwrap();
+
+    for (a, b) in [(false, false), (true, false), (false, true), (true, true)] {
+      assert_eq!(
+        simulation.run_and_read(&[a, b], &[s, c]),
+        vec![a != b, a && b],
+        "{} + {}",
+        a,
+        b
+      );
+    }
+    assert_eq!(
+      simulation.run_and_read(&[true, true], &[c, c]),
+      [true, true]
+    );
+  }
+
+  #[test]
+  /// Test that only the outputs of gates are visible, not the registers
+  /// inside them
+  fn visible_values() {
+    let mut compiler = Compiler::new(2);
+    let s = compiler.alloc();
+    let c = compiler.alloc();
+    let out = compiler.alloc();
+    let mut simulation = compiler
+      .compile(vec![&Gate::from(HalfAdder { a: 0, b: 1, s, c })])
+      .unwrap();
+    simulation.run(&[true, false]);
+
+    assert_eq!(simulation.value_map().len(), simulation.registers.len());
+    assert!(simulation.registers.len() > 4);
+    assert_eq!(
+      simulation.visible_values().collect::<Vec<_>>(),
+      vec![(s, true), (c, false)]
+    );
+
+    // Appended gates are visible too
+    compiler
+      .append(&Gate::from(And { a: s, b: c, out }), &mut simulation)
+      .unwrap();
+    let visible: Vec<usize> =
+      simulation.visible_values().map(|(reg, _)| reg).collect();
+    assert_eq!(visible, vec![s, c, out]);
+  }
+
+  #[test]
+  /// Test that a persistent enable stays high while only the clock is passed
+  fn persistent_input() {
+    let mut compiler = Compiler::new(2);
+    let [clk, enable] = [0, 1];
+    let flip_flop = DFlipFlop {
+      d: enable,
+      clk,
+      q: compiler.alloc(),
+    };
+    let mut simulation =
+      compiler.compile(vec![&Gate::from(flip_flop)]).unwrap();
+
+    simulation.set_persistent_input(enable, true);
+    for clk in [false, true, false, true, false] {
+      assert!(simulation.run_until_stable(&[clk], 10));
+      assert!(simulation.registers[enable]);
+    }
+    assert!(simulation.registers[flip_flop.q]);
+
+    // Passing the enable doesn't override it either
+    simulation.run(&[true, false]);
+    assert!(simulation.registers[enable]);
+
+    // The next rising edge captures the enable low
+    simulation.clear_persistent_inputs();
+    for clk in [false, true] {
+      assert!(simulation.run_until_stable(&[clk], 10));
+    }
+    assert!(!simulation.registers[enable]);
+    assert!(!simulation.registers[flip_flop.q]);
+  }
+
+  #[test]
+  /// Test that replaying a saved log from the same state reproduces the
+  /// registers
+  fn replay_log() {
+    let mut compiler = Compiler::new(2);
+    let [d, clk] = [0, 1];
+    let flip_flop = DFlipFlop {
+      d,
+      clk,
+      q: compiler.alloc(),
+    };
+    let mut simulation =
+      compiler.compile(vec![&Gate::from(flip_flop)]).unwrap();
+    let fresh = simulation.clone();
+
+    simulation.enable_log();
+    for immediates in [[true, false], [true, true], [false, false]] {
+      assert!(simulation.run_until_stable(&immediates, 10));
+    }
+    // Every run it takes to settle is logged
+    assert!(simulation.replay_log().len() > 3);
+    assert!(simulation.registers[flip_flop.q]);
+
+    let path = env::temp_dir().join("complogic_replay_log.json");
+    simulation.save_log(&path).unwrap();
+    let log = Simulation::load_log(&path).unwrap();
+    assert_eq!(log, simulation.replay_log());
+
+    let mut replayed = fresh;
+    replayed.replay(&log);
+    assert_eq!(replayed.registers, simulation.registers);
+    assert!(replayed.replay_log().is_empty());
+  }
+
+  #[test]
+  /// Test that every register of a full adder toggles under exhaustive inputs
+  fn toggle_coverage_full_adder() {
+    let mut compiler = Compiler::new(3);
+    let [s, cout] = [compiler.alloc(), compiler.alloc()];
+    let full_adder = FullAdder {
+      a: 0,
+      b: 1,
+      cin: 2,
+      s,
+      cout,
+    };
+    let mut simulation =
+      compiler.compile(vec![&Gate::from(full_adder)]).unwrap();
+
+    simulation.run(&[true, true, true]);
+    assert!(simulation
+      .toggle_coverage()
+      .iter()
+      .all(|(_, low, high)| !low && !high));
+
+    simulation.reset_coverage();
+    simulation.run(&[false, false, false]);
+    assert!(simulation
+      .toggle_coverage()
+      .iter()
+      .any(|(_, low, high)| !(*low && *high)));
+
+    for value in 0..8 {
+      simulation.run(&to_bits(value, 3));
+    }
+    let coverage = simulation.toggle_coverage();
+    assert_eq!(coverage.len(), simulation.registers.len());
+    for (reg, low, high) in coverage {
+      assert!(low && high, "r{} didn't toggle", reg);
+    }
+  }
+
+  #[test]
+  /// Test that a gate reading a disconnected register is flagged as constant
+  fn constant_registers_disconnected() {
+    let mut compiler = Compiler::new(2);
+    let [xor, disconnected, and] =
+      [compiler.alloc(), compiler.alloc(), compiler.alloc()];
+    let gates = [
+      Gate::from(Xor {
+        a: 0,
+        b: 1,
+        out: xor,
+      }),
+      Gate::from(And {
+        a: 0,
+        b: disconnected,
+        out: and,
+      }),
+    ];
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+
+    simulation.run(&[true, true]);
+    assert!(simulation.constant_registers().is_empty());
+
+    simulation.reset_coverage();
+    for value in 0..4 {
+      simulation.run(&to_bits(value, 2));
+    }
+    let constant = simulation.constant_registers();
+    assert!(constant.contains(&(disconnected, false)), "{:?}", constant);
+    assert!(constant.contains(&(and, false)), "{:?}", constant);
+    assert!(constant.iter().all(|(reg, _)| ![0, 1, xor].contains(reg)));
+  }
+
+  #[test]
+  /// Test that the feedback of a latch that was never written to is unknown
+  /// until it's set, and then holds its value
+  fn three_valued_latch() {
+    let mut compiler = Compiler::new(2);
+    let [s, r] = [0, 1];
+    let rs_latch = RSLatch {
+      s,
+      r,
+      q: compiler.alloc(),
+      initial: None,
+    };
+    let mut simulation = compiler.compile(vec![&Gate::from(rs_latch)]).unwrap();
+    simulation.enable_three_valued();
+    assert_eq!(simulation.logic_value(rs_latch.q), Logic::X);
+
+    // Holding never settles the feedback
+    for _ in 0..3 {
+      simulation.run_logic(&[Logic::Zero, Logic::Zero]);
+      assert_eq!(simulation.logic_value(rs_latch.q), Logic::X);
+    }
+
+    for (immediates, q) in [
+      ([Logic::One, Logic::Zero], Logic::One),
+      ([Logic::Zero, Logic::Zero], Logic::One),
+      ([Logic::Zero, Logic::One], Logic::Zero),
+      // An unknown reset doesn't matter once reset, but an unknown set does
+      ([Logic::Zero, Logic::X], Logic::Zero),
+      ([Logic::X, Logic::Zero], Logic::X),
+    ] {
+      simulation.run_logic(&immediates);
+      simulation.run_logic(&immediates);
+      assert_eq!(simulation.logic_value(rs_latch.q), q, "{:?}", immediates);
+    }
+
+    // The two-valued registers are left alone
+    assert!(simulation.registers.iter().all(|reg| !reg));
+  }
+
+  #[test]
+  /// Test that stepping through every op matches a run
+  fn step() {
+    let mut compiler = Compiler::new(3);
+    let [s, cout] = [compiler.alloc(), compiler.alloc()];
+    let full_adder = FullAdder {
+      a: 0,
+      b: 1,
+      cin: 2,
+      s,
+      cout,
+    };
+    let mut simulation =
+      compiler.compile(vec![&Gate::from(full_adder)]).unwrap();
+    let mut stepped = simulation.clone();
+
+    for immediates in [[true, false, true], [true, true, true]] {
+      simulation.run(&immediates);
+
+      let mut pc = stepped.step(0, &immediates);
+      let mut steps = 1;
+      while pc != 0 {
+        assert_eq!(pc, steps);
+        pc = stepped.step(pc, &immediates);
+        steps += 1;
+      }
+
+      assert_eq!(steps, stepped.op_count());
+      assert_eq!(stepped.registers, simulation.registers);
+    }
+  }
+
+  #[test]
+  /// Test that a simulation built from ops runs them
+  fn from_ops() {
+    let ops = vec![
+      Op::Set(0, false),
+      Op::Set(1, false),
+      Op::Nand(0, 1, 2),
+      Op::Nand(2, 2, 3),
+    ];
+    let mut simulation = Simulation::from_ops(ops, 4).unwrap();
+
+    for (a, b) in [(false, false), (true, false), (true, true)] {
+      simulation.run(&[a, b]);
+      assert_eq!(simulation.registers[3], a && b);
+    }
+  }
+
+  #[test]
+  /// Test that ops with registers out of range or shared outputs are
+  /// rejected
+  fn from_ops_errors() {
+    assert_eq!(
+      Simulation::from_ops(vec![Op::Nand(0, 1, 2), Op::Nand(2, 4, 3)], 4)
+        .unwrap_err(),
+      BuildError::RegisterOutOfRange {
+        op: 1,
+        reg: 4,
+        register_count: 4
+      }
+    );
+    assert_eq!(
+      Simulation::from_ops(vec![Op::Nand(0, 1, 2), Op::Nand(1, 0, 2)], 3)
+        .unwrap_err(),
+      BuildError::MultipleDrivers { reg: 2 }
+    );
+  }
+
+  #[test]
+  /// Test that a strict run errors when immediates are missing
+  fn run_strict_too_short() {
+    let mut simulation = Simulation {
+      registers: vec![false, false, false],
+      ops: vec![Op::Set(0, false), Op::Set(1, true), Op::Nand(0, 1, 2)],
+      immediate_count: 2,
+      ..Default::default()
+    };
+
+    assert_eq!(
+      simulation.run_strict(&[true]),
+      Err(RunError::ImmediateCount {
+        expected: 2,
+        found: 1
+      })
+    );
+
+    // The simulation shouldn't run when the immediates don't match
+    assert!(!simulation.registers[2]);
+
+    assert_eq!(simulation.run_strict(&[true, true]), Ok(()));
+    assert!(!simulation.registers[2]);
+  }
+
+  #[test]
+  /// Test that the DOT export has a node for each op and an edge per input
+  fn to_dot() {
+    let simulation = Simulation {
+      registers: vec![false, false, false, false],
+      ops: vec![
+        Op::Set(0, false),
+        Op::Set(1, false),
+        Op::Nand(0, 1, 2),
+        Op::Nand(2, 2, 3),
+      ],
+      immediate_count: 2,
+      ..Default::default()
+    };
+
+    let dot = simulation.to_dot();
+    assert!(dot.starts_with("digraph {"));
+    assert!(dot.trim_end().ends_with('}'));
+    assert_eq!(dot.matches("[ label = ").count(), simulation.ops.len());
+    assert_eq!(dot.matches(" -> ").count(), 3);
+    assert!(dot.contains("r2 = Nand(r0, r1)"));
+  }
+
+  #[test]
+  /// Test that the truth table of an Xor sweeps both of its inputs
+  fn truth_table() {
+    let mut compiler = Compiler::new(2);
+    let xor = Xor {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let simulation = compiler.compile(vec![&Gate::from(xor)]).unwrap();
+
+    assert_eq!(
+      simulation.truth_table(&[0, 1], &[xor.out]),
+      vec![
+        (vec![false, false], vec![false]),
+        (vec![false, true], vec![true]),
+        (vec![true, false], vec![true]),
+        (vec![true, true], vec![false]),
+      ]
+    );
+    assert!(simulation.registers.iter().all(|reg| !reg));
+  }
+
+  #[test]
+  /// Test that numbers round-trip through registers, most significant bit
+  /// first, and that an adder's sum reads back as a number
+  fn read_write_number() {
+    let mut compiler = Compiler::new(8);
+    let adder = RippleCarryAdder {
+      a: vec![0, 1, 2, 3],
+      b: vec![4, 5, 6, 7],
+      sum: (0..4).map(|_| compiler.alloc()).collect(),
+      cout: compiler.alloc(),
+      overflow: OverflowMode::Wrap,
+    };
+    let mut simulation =
+      compiler.compile(vec![&Gate::from(adder.clone())]).unwrap();
+
+    // The adder's numbers are least significant bit first
+    let a: Vec<usize> = adder.a.iter().rev().copied().collect();
+    let b: Vec<usize> = adder.b.iter().rev().copied().collect();
+    simulation.write_number(&a, 0b1011);
+    assert_eq!(simulation.read_number(&a), 0b1011);
+    assert_eq!(&simulation.registers[0..4], &[true, true, false, true]);
+
+    // Bits that don't fit are dropped
+    simulation.write_number(&a, 0b10110);
+    assert_eq!(simulation.read_number(&a), 0b0110);
+
+    let mut sum = vec![adder.cout];
+    sum.extend(adder.sum.iter().rev());
+    for (x, y) in [(0, 0), (5, 9), (15, 15), (8, 7)] {
+      simulation.write_number(&a, x);
+      simulation.write_number(&b, y);
+      let immediates = simulation.registers[0..8].to_vec();
+      simulation.run(&immediates);
+      assert_eq!(simulation.read_number(&sum), x + y, "{} + {}", x, y);
+    }
+  }
+
+  #[test]
+  /// Test that an incremental run matches a full run after changing one
+  /// immediate of a four-bit adder
+  fn run_incremental() {
+    let mut compiler = Compiler::new(8);
+    let adder = FourBitAdder {
+      a1: 0,
+      a2: 1,
+      a3: 2,
+      a4: 3,
+      b1: 4,
+      b2: 5,
+      b3: 6,
+      b4: 7,
+      s1: compiler.alloc(),
+      s2: compiler.alloc(),
+      s3: compiler.alloc(),
+      s4: compiler.alloc(),
+      cout: compiler.alloc(),
+    };
+    let mut full = compiler.compile(vec![&Gate::from(adder)]).unwrap();
+
+    let mut immediates = [true, true, false, true, true, false, false, true];
+    full.run(&immediates);
+    let mut incremental = full.clone();
+
+    for (reg, val) in [(0, false), (2, true), (7, false), (0, true)] {
+      immediates[reg] = val;
+      full.run(&immediates);
+      incremental.run_incremental(&[(reg, val)]).unwrap();
+      assert_eq!(incremental.registers, full.registers);
+    }
+  }
+
+  #[test]
+  /// Test that an incremental run is logged and keeps the persistent inputs
+  /// and faults like a full run
+  fn run_incremental_like_run() {
+    let mut compiler = Compiler::new(2);
+    let and = And {
+      a: 0,
+      b: 1,
+      out: compiler.alloc(),
+    };
+    let not = Not {
+      a: and.out,
+      out: compiler.alloc(),
+    };
+    let mut full = compiler
+      .compile(vec![&Gate::from(and), &Gate::from(not)])
+      .unwrap();
+    full.run(&[true, false]);
+    full.enable_log();
+    full.set_persistent_input(1, true);
+    full.inject_stuck_at(not.out, true);
+    let mut incremental = full.clone();
+
+    full.run(&[false, false]);
+    incremental
+      .run_incremental(&[(0, false), (1, false)])
+      .unwrap();
+    assert_eq!(incremental.registers, full.registers);
+    assert_eq!(incremental.replay_log(), &[vec![false, false]]);
+
+    full.run(&[true, false]);
+    incremental.run_incremental(&[(0, true)]).unwrap();
+    assert_eq!(incremental.registers, full.registers);
+    assert!(incremental.registers[and.out]);
+    assert!(incremental.registers[not.out]);
+
+    // Registers past the immediates are rejected before anything changes
+    let registers = incremental.registers.clone();
+    assert_eq!(
+      incremental.run_incremental(&[(0, false), (and.out, false)]),
+      Err(RunError::NotAnImmediate {
+        reg: and.out,
+        immediate_count: 2,
+      })
+    );
+    assert_eq!(incremental.registers, registers);
+    assert_eq!(incremental.replay_log().len(), 2);
+  }
+
+  #[test]
+  /// Test that the compiled function gives the same registers as running a
+  /// four-bit adder with a fault
+  fn compile_fn() {
+    let mut compiler = Compiler::new(8);
+    let adder = FourBitAdder {
+      a1: 0,
+      a2: 1,
+      a3: 2,
+      a4: 3,
+      b1: 4,
+      b2: 5,
+      b3: 6,
+      b4: 7,
+      s1: compiler.alloc(),
+      s2: compiler.alloc(),
+      s3: compiler.alloc(),
+      s4: compiler.alloc(),
+      cout: compiler.alloc(),
+    };
+    let mut simulation = compiler.compile(vec![&Gate::from(adder)]).unwrap();
+    let stuck = op_output(simulation.ops[simulation.ops.len() / 2]);
+    simulation.inject_stuck_at(stuck, true);
+
+    let run = simulation.compile_fn();
+    let mut registers = simulation.registers.clone();
+    for inputs in 0..1 << 8 {
+      let immediates = to_bits(inputs, 8);
+      simulation.run(&immediates);
+      run(&mut registers, &immediates);
+      assert_eq!(registers, simulation.registers, "inputs {:08b}", inputs);
+    }
+  }
+
+  #[test]
+  /// Test that folding constants gives the same registers as the Nands
+  fn fold_constants() {
+    // Registers 1 and 2 are never written to, so they stay high and low
+    let simulation = Simulation {
+      registers: vec![false, true, false, false, false, false, false, false],
+      ops: vec![
+        Op::Set(0, false),
+        Op::Nand(0, 1, 3),
+        Op::Nand(0, 2, 4),
+        Op::Nand(4, 4, 5),
+        Op::Nand(3, 3, 6),
+        Op::Nand(0, 3, 7),
+      ],
+      immediate_count: 1,
+      ..Default::default()
+    };
+
+    let mut folded = simulation.clone();
+    folded.fold_constants();
+    assert_eq!(
+      folded.ops,
+      vec![
+        Op::Set(0, false),
+        Op::Inv(0, 3),
+        Op::Buf(0, 6),
+        Op::Nand(0, 3, 7),
+      ]
+    );
+
+    let mut simulation = simulation;
+    for immediates in [[false], [true], [true], [false]] {
+      simulation.run(&immediates);
+      folded.run(&immediates);
+      assert_eq!(simulation.registers, folded.registers);
+    }
+  }
+
+  #[test]
+  /// Test that folding a simulation built from ops doesn't treat its Sets as
+  /// constant, since they read the immediates of each run
+  fn fold_constants_from_ops() {
+    let mut simulation = Simulation::from_ops(
+      vec![Op::Set(0, false), Op::Nand(0, 0, 1), Op::Nand(2, 2, 3)],
+      4,
+    )
+    .unwrap();
+    simulation.delays = vec![1, 2, 3];
+
+    let mut folded = simulation.clone();
+    folded.fold_constants();
+    assert_eq!(folded.ops, vec![Op::Set(0, false), Op::Inv(0, 1)]);
+    assert_eq!(folded.delays, vec![1, 2]);
+
+    for immediates in [vec![true], vec![false], vec![true, false]] {
+      simulation.run(&immediates);
+      folded.run(&immediates);
+      assert_eq!(simulation.registers, folded.registers, "{:?}", immediates);
+    }
+  }
+
+  #[test]
+  /// Test that probes read the registers they watch by name
+  fn probes() {
+    let mut compiler = Compiler::new(2);
+    let [s, c] = [compiler.alloc(), compiler.alloc()];
+    let mut simulation = compiler
+      .compile(vec![&Gate::from(HalfAdder { a: 0, b: 1, s, c })])
+      .unwrap();
+
+    simulation.add_probe(s, "sum");
+    simulation.add_probe(c, "carry");
+    assert_eq!(
+      simulation.probes(),
+      vec![("sum".to_string(), false), ("carry".to_string(), false)]
+    );
+
+    simulation.run(&[true, false]);
+    assert_eq!(
+      simulation.probes(),
+      vec![("sum".to_string(), true), ("carry".to_string(), false)]
+    );
+
+    simulation.run(&[true, true]);
+    assert_eq!(
+      simulation.probes(),
+      vec![("sum".to_string(), false), ("carry".to_string(), true)]
+    );
+
+    // Adding a probe with a taken name moves it
+    simulation.add_probe(0, "sum");
+    assert_eq!(simulation.probes()[0], ("sum".to_string(), true));
+    assert_eq!(simulation.watched.len(), 2);
+  }
+
+  #[test]
+  /// Test that running by name matches running with the immediates in order
+  fn run_named() {
+    let mut compiler = Compiler::new(3);
+    let adder = FullAdder {
+      a: 0,
+      b: 1,
+      cin: 2,
+      s: compiler.alloc(),
+      cout: compiler.alloc(),
+    };
+    for (reg, name) in [(0, "a"), (1, "b"), (2, "cin"), (adder.s, "s")] {
+      compiler.label(reg, name);
+    }
+    let mut named = compiler.compile(vec![&Gate::from(adder)]).unwrap();
+    let mut positional = named.clone();
+    assert_eq!(named.labels.len(), 4);
+
+    named
+      .run_named(&HashMap::from([
+        ("a".to_string(), true),
+        ("cin".to_string(), true),
+      ]))
+      .unwrap();
+    positional.run(&[true, false, true]);
+
+    assert_eq!(named.registers, positional.registers);
+    assert!(!named.registers[adder.s]);
+    assert!(named.registers[adder.cout]);
+
+    // Outputs aren't inputs, and nothing runs when a name is wrong
+    for name in ["s", "carry"] {
+      assert_eq!(
+        named.run_named(&HashMap::from([(name.to_string(), true)])),
+        Err(RunError::UnknownInput(name.to_string()))
+      );
+    }
+    assert_eq!(named.registers, positional.registers);
+  }
+
+  #[test]
+  /// Test that a change moves one inverter along a chain per unit-delay step
+  fn run_unit_delay_chain() {
+    let mut compiler = Compiler::new(1);
+    let mut gates = vec![];
+    let mut stages = vec![];
+    let mut a = 0;
+    for _ in 0..4 {
+      let out = compiler.alloc();
+      gates.push(Gate::from(Not { a, out }));
+      stages.push(out);
+      a = out;
+    }
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+    simulation.run(&[false]);
+
+    let snapshots = simulation.run_unit_delay(&[true], 5);
+    assert_eq!(snapshots.len(), 5);
+    for (step, registers) in snapshots.iter().enumerate() {
+      for (stage, reg) in stages.iter().enumerate() {
+        // Stage k has flipped from its settled value after step k
+        let settled = stage % 2 == 0;
+        assert_eq!(
+          registers[*reg],
+          settled != (stage <= step),
+          "stage {} after step {}",
+          stage,
+          step + 1
+        );
+      }
+    }
+    assert_eq!(simulation.registers, snapshots[4]);
+  }
+
+  #[test]
+  /// Test that a unit-delay run is logged and counts toward the coverage
+  fn run_unit_delay_log_coverage() {
+    let mut compiler = Compiler::new(1);
+    let not = Not {
+      a: 0,
+      out: compiler.alloc(),
+    };
+    let mut simulation = compiler.compile(vec![&Gate::from(not)]).unwrap();
+    simulation.enable_log();
+    simulation.reset_coverage();
+
+    simulation.run(&[false]);
+    simulation.run_unit_delay(&[true], 2);
+    assert_eq!(simulation.replay_log(), &[vec![false], vec![true]]);
+    assert_eq!(
+      simulation.toggle_coverage(),
+      vec![(0, true, true), (not.out, true, true)]
+    );
+  }
 }
@@ -0,0 +1,718 @@
+use std::fmt;
+
+use crate::{
+  And, And3, AsyncCounter, Buffer, Constant, DFlipFlop, DFlipFlopSR, DLatch,
+  Encoder4to2, FourBitAdder, FullAdder, Gate, GatedSRLatch, HalfAdder,
+  MagnitudeComparator, Multiplier, Mux2, MuxWide, Nand, NandRSLatch, Nor, Not,
+  Or, Or3, OverflowMode, Polarity, RSLatch, RippleCarryAdder, Rom,
+  ShiftRegister, TriState, Xor,
+};
+
+/// The names of the gates that can be built from a spec
+pub const GATE_NAMES: [&str; 29] = [
+  "Nand",
+  "Not",
+  "Buffer",
+  "And",
+  "Or",
+  "Nor",
+  "Xor",
+  "And3",
+  "Or3",
+  "Constant",
+  "Encoder4to2",
+  "TriState",
+  "Mux2",
+  "MuxWide",
+  "Rom",
+  "RSLatch",
+  "NandRSLatch",
+  "GatedSRLatch",
+  "DLatch",
+  "DFlipFlop",
+  "DFlipFlopSR",
+  "ShiftRegister",
+  "AsyncCounter",
+  "HalfAdder",
+  "FullAdder",
+  "FourBitAdder",
+  "RippleCarryAdder",
+  "MagnitudeComparator",
+  "Multiplier",
+];
+
+/// Describes a kind of gate for building circuits, such as in a node finder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GateInfo {
+  /// The name of the gate, as used by [`gate_from_spec`]
+  pub name: &'static str,
+
+  /// The group the gate is listed under
+  pub category: &'static str,
+
+  /// What the gate does, in a sentence
+  pub description: &'static str,
+
+  /// The names of the inputs, in order. Names ending in `[]` take any number
+  /// of registers.
+  pub inputs: &'static [&'static str],
+
+  /// The names of the outputs, in order. Names ending in `[]` take any
+  /// number of registers.
+  pub outputs: &'static [&'static str],
+}
+
+/// Lists every gate that can be built from a spec, with its metadata
+pub fn gate_catalog() -> Vec<GateInfo> {
+  let info = |name, category, description, inputs, outputs| GateInfo {
+    name,
+    category,
+    description,
+    inputs,
+    outputs,
+  };
+
+  vec![
+    info(
+      "Nand",
+      "Basic",
+      "Low only when both inputs are high",
+      &["a", "b"],
+      &["out"],
+    ),
+    info("Not", "Basic", "Inverts the input", &["a"], &["out"]),
+    info(
+      "Buffer",
+      "Basic",
+      "Copies the input to its own register",
+      &["a"],
+      &["out"],
+    ),
+    info(
+      "And",
+      "Basic",
+      "High when both inputs are high",
+      &["a", "b"],
+      &["out"],
+    ),
+    info(
+      "Or",
+      "Basic",
+      "High when either input is high",
+      &["a", "b"],
+      &["out"],
+    ),
+    info(
+      "Nor",
+      "Basic",
+      "High when neither input is high",
+      &["a", "b"],
+      &["out"],
+    ),
+    info(
+      "Xor",
+      "Basic",
+      "High when the inputs differ",
+      &["a", "b"],
+      &["out"],
+    ),
+    info(
+      "And3",
+      "Basic",
+      "High when all three inputs are high",
+      &["a", "b", "c"],
+      &["out"],
+    ),
+    info(
+      "Or3",
+      "Basic",
+      "High when any of the three inputs are high",
+      &["a", "b", "c"],
+      &["out"],
+    ),
+    info(
+      "Constant",
+      "Basic",
+      "Always low, such as to tie off an unused input",
+      &[],
+      &["out"],
+    ),
+    info(
+      "Encoder4to2",
+      "Combinational",
+      "Encodes the highest of four inputs as two bits",
+      &["in_0", "in_1", "in_2", "in_3"],
+      &["out_0", "out_1"],
+    ),
+    info(
+      "TriState",
+      "Combinational",
+      "Passes the input through while enabled, otherwise low",
+      &["input", "enable"],
+      &["out"],
+    ),
+    info(
+      "Mux2",
+      "Combinational",
+      "Passes a through while sel is low and b while it's high",
+      &["a", "b", "sel"],
+      &["out"],
+    ),
+    info(
+      "MuxWide",
+      "Combinational",
+      "Passes the bus a through while sel is low and b while it's high",
+      &["a[]", "b[]", "sel"],
+      &["out[]"],
+    ),
+    info(
+      "Rom",
+      "Combinational",
+      "Reads the word stored at the address, every word being low",
+      &["address[]"],
+      &["data[]"],
+    ),
+    info(
+      "RSLatch",
+      "Sequential",
+      "Set by s and reset by r",
+      &["s", "r"],
+      &["q"],
+    ),
+    info(
+      "NandRSLatch",
+      "Sequential",
+      "Set by a low s and reset by a low r, set when both are low",
+      &["s", "r"],
+      &["q"],
+    ),
+    info(
+      "GatedSRLatch",
+      "Sequential",
+      "An RS latch that only changes while enabled",
+      &["s", "r", "e"],
+      &["q"],
+    ),
+    info(
+      "DLatch",
+      "Sequential",
+      "Follows d while enabled",
+      &["d", "e"],
+      &["q"],
+    ),
+    info(
+      "DFlipFlop",
+      "Sequential",
+      "Captures d on the rising edge of the clock",
+      &["d", "clk"],
+      &["q"],
+    ),
+    info(
+      "DFlipFlopSR",
+      "Sequential",
+      "A D flip-flop that preset forces high and clear forces low",
+      &["d", "clk", "preset", "clear"],
+      &["q"],
+    ),
+    info(
+      "ShiftRegister",
+      "Sequential",
+      "Shifts the serial input along q on each rising edge of the clock",
+      &["serial_in", "clk"],
+      &["q[]"],
+    ),
+    info(
+      "AsyncCounter",
+      "Sequential",
+      "Counts the rising edges of the clock, cleared right away by reset",
+      &["clk", "reset"],
+      &["q[]"],
+    ),
+    info(
+      "HalfAdder",
+      "Arithmetic",
+      "Adds two bits",
+      &["a", "b"],
+      &["s", "c"],
+    ),
+    info(
+      "FullAdder",
+      "Arithmetic",
+      "Adds two bits and a carry",
+      &["a", "b", "cin"],
+      &["s", "cout"],
+    ),
+    info(
+      "FourBitAdder",
+      "Arithmetic",
+      "Adds two four-bit numbers",
+      &["a1", "a2", "a3", "a4", "b1", "b2", "b3", "b4"],
+      &["s1", "s2", "s3", "s4", "cout"],
+    ),
+    info(
+      "RippleCarryAdder",
+      "Arithmetic",
+      "Adds two numbers of the same width, wrapping on overflow",
+      &["a[]", "b[]"],
+      &["sum[]", "cout"],
+    ),
+    info(
+      "MagnitudeComparator",
+      "Arithmetic",
+      "Compares two numbers of the same width, after any lower bits",
+      &["a[]", "b[]", "gt_in", "lt_in", "eq_in"],
+      &["gt", "lt", "eq"],
+    ),
+    info(
+      "Multiplier",
+      "Arithmetic",
+      "Multiplies two numbers of the same width",
+      &["a[]", "b[]"],
+      &["product[]"],
+    ),
+  ]
+}
+
+/// Gets the metadata of a gate by its name
+pub fn gate_info(name: &str) -> Option<GateInfo> {
+  gate_catalog().into_iter().find(|info| info.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+  /// There's no gate with the name
+  UnknownName(String),
+
+  /// The gate can't be wired with this many inputs and outputs
+  Arity {
+    name: String,
+    inputs: usize,
+    outputs: usize,
+  },
+
+  /// The ROM has too many address bits to have a word for each address
+  AddressTooWide { bits: usize },
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::UnknownName(name) => write!(f, "unknown gate {}", name),
+      ParseError::Arity {
+        name,
+        inputs,
+        outputs,
+      } => {
+        write!(
+          f,
+          "{} can't have {} inputs and {} outputs",
+          name, inputs, outputs
+        )
+      }
+      ParseError::AddressTooWide { bits } => {
+        write!(f, "a ROM can't have {} address bits", bits)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Builds a gate from its name (see [`Gate::kind_name`]) and its registers
+///
+/// The inputs and outputs are in the same order as [`Gate::inputs`] and
+/// [`Gate::outputs`]. The inputs of adders, comparators and multipliers are
+/// split in half between `a` and `b` (before the cascade inputs of a
+/// comparator), as are the buses of a wide mux before its select, and adders
+/// wrap on overflow. A spec only has the registers of a gate, so constants
+/// are low and every word of a ROM is low.
+pub fn gate_from_spec(
+  name: &str,
+  inputs: &[usize],
+  outputs: &[usize],
+) -> Result<Gate, ParseError> {
+  let gate = match (name, inputs, outputs) {
+    ("Nand", [a, b], [out]) => Gate::from(Nand {
+      a: *a,
+      b: *b,
+      out: *out,
+    }),
+    ("Not", [a], [out]) => Gate::from(Not { a: *a, out: *out }),
+    ("Buffer", [a], [out]) => Gate::from(Buffer {
+      a: *a,
+      out: *out,
+      invert: false,
+    }),
+    ("And", [a, b], [out]) => Gate::from(And {
+      a: *a,
+      b: *b,
+      out: *out,
+    }),
+    ("Or", [a, b], [out]) => Gate::from(Or {
+      a: *a,
+      b: *b,
+      out: *out,
+    }),
+    ("Nor", [a, b], [out]) => Gate::from(Nor {
+      a: *a,
+      b: *b,
+      out: *out,
+    }),
+    ("Xor", [a, b], [out]) => Gate::from(Xor {
+      a: *a,
+      b: *b,
+      out: *out,
+    }),
+    ("And3", [a, b, c], [out]) => Gate::from(And3 {
+      a: *a,
+      b: *b,
+      c: *c,
+      out: *out,
+    }),
+    ("Or3", [a, b, c], [out]) => Gate::from(Or3 {
+      a: *a,
+      b: *b,
+      c: *c,
+      out: *out,
+    }),
+    ("Constant", [], [out]) => Gate::from(Constant {
+      value: false,
+      out: *out,
+    }),
+    ("Encoder4to2", [a, b, c, d], [out_0, out_1]) => Gate::from(Encoder4to2 {
+      inputs: [*a, *b, *c, *d],
+      out: [*out_0, *out_1],
+    }),
+    ("TriState", [input, enable], [out]) => Gate::from(TriState {
+      input: *input,
+      enable: *enable,
+      out: *out,
+    }),
+    ("Mux2", [a, b, sel], [out]) => Gate::from(Mux2 {
+      a: *a,
+      b: *b,
+      sel: *sel,
+      out: *out,
+    }),
+    ("MuxWide", [buses @ .., sel], out)
+      if !out.is_empty() && buses.len() == 2 * out.len() =>
+    {
+      let (a, b) = buses.split_at(out.len());
+      Gate::from(MuxWide {
+        a: a.to_vec(),
+        b: b.to_vec(),
+        sel: *sel,
+        out: out.to_vec(),
+      })
+    }
+    ("Rom", address, data) if !address.is_empty() && !data.is_empty() => {
+      let words = u32::try_from(address.len())
+        .ok()
+        .and_then(|bits| 1usize.checked_shl(bits))
+        .ok_or(ParseError::AddressTooWide {
+          bits: address.len(),
+        })?;
+
+      Gate::from(Rom {
+        address: address.to_vec(),
+        data: data.to_vec(),
+        contents: vec![vec![false; data.len()]; words],
+      })
+    }
+    ("RSLatch", [s, r], [q]) => Gate::from(RSLatch {
+      s: *s,
+      r: *r,
+      q: *q,
+      initial: None,
+    }),
+    ("NandRSLatch", [s, r], [q]) => Gate::from(NandRSLatch {
+      s: *s,
+      r: *r,
+      q: *q,
+    }),
+    ("GatedSRLatch", [s, r, e], [q]) => Gate::from(GatedSRLatch {
+      s: *s,
+      r: *r,
+      e: *e,
+      q: *q,
+    }),
+    ("DLatch", [d, e], [q]) => Gate::from(DLatch {
+      d: *d,
+      e: *e,
+      q: *q,
+      polarity: Polarity::High,
+    }),
+    ("DFlipFlop", [d, clk], [q]) => Gate::from(DFlipFlop {
+      d: *d,
+      clk: *clk,
+      q: *q,
+    }),
+    ("DFlipFlopSR", [d, clk, preset, clear], [q]) => Gate::from(DFlipFlopSR {
+      d: *d,
+      clk: *clk,
+      preset: *preset,
+      clear: *clear,
+      q: *q,
+    }),
+    ("ShiftRegister", [serial_in, clk], q) if !q.is_empty() => {
+      Gate::from(ShiftRegister {
+        serial_in: *serial_in,
+        clk: *clk,
+        q: q.to_vec(),
+      })
+    }
+    ("AsyncCounter", [clk, reset], q) if !q.is_empty() => {
+      Gate::from(AsyncCounter {
+        clk: *clk,
+        reset: *reset,
+        q: q.to_vec(),
+      })
+    }
+    ("HalfAdder", [a, b], [s, c]) => Gate::from(HalfAdder {
+      a: *a,
+      b: *b,
+      s: *s,
+      c: *c,
+    }),
+    ("FullAdder", [a, b, cin], [s, cout]) => Gate::from(FullAdder {
+      a: *a,
+      b: *b,
+      cin: *cin,
+      s: *s,
+      cout: *cout,
+    }),
+    (
+      "FourBitAdder",
+      [a1, a2, a3, a4, b1, b2, b3, b4],
+      [s1, s2, s3, s4, cout],
+    ) => Gate::from(FourBitAdder {
+      a1: *a1,
+      a2: *a2,
+      a3: *a3,
+      a4: *a4,
+      b1: *b1,
+      b2: *b2,
+      b3: *b3,
+      b4: *b4,
+      s1: *s1,
+      s2: *s2,
+      s3: *s3,
+      s4: *s4,
+      cout: *cout,
+    }),
+    ("RippleCarryAdder", inputs, [sum @ .., cout])
+      if !inputs.is_empty()
+        && inputs.len() % 2 == 0
+        && sum.len() == inputs.len() / 2 =>
+    {
+      let (a, b) = inputs.split_at(inputs.len() / 2);
+      Gate::from(RippleCarryAdder {
+        a: a.to_vec(),
+        b: b.to_vec(),
+        sum: sum.to_vec(),
+        cout: *cout,
+        overflow: OverflowMode::Wrap,
+      })
+    }
+    (
+      "MagnitudeComparator",
+      [numbers @ .., gt_in, lt_in, eq_in],
+      [gt, lt, eq],
+    ) if !numbers.is_empty() && numbers.len() % 2 == 0 => {
+      let (a, b) = numbers.split_at(numbers.len() / 2);
+      Gate::from(MagnitudeComparator {
+        a: a.to_vec(),
+        b: b.to_vec(),
+        gt_in: *gt_in,
+        lt_in: *lt_in,
+        eq_in: *eq_in,
+        gt: *gt,
+        lt: *lt,
+        eq: *eq,
+      })
+    }
+    ("Multiplier", inputs, product)
+      if !inputs.is_empty()
+        && inputs.len() % 2 == 0
+        && product.len() == inputs.len() =>
+    {
+      let (a, b) = inputs.split_at(inputs.len() / 2);
+      Gate::from(Multiplier {
+        a: a.to_vec(),
+        b: b.to_vec(),
+        product: product.to_vec(),
+      })
+    }
+    _ if GATE_NAMES.contains(&name) => {
+      return Err(ParseError::Arity {
+        name: name.to_string(),
+        inputs: inputs.len(),
+        outputs: outputs.len(),
+      })
+    }
+    _ => return Err(ParseError::UnknownName(name.to_string())),
+  };
+
+  Ok(gate)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Compiler;
+
+  #[test]
+  /// Test that an And built from a spec works like a native And
+  fn and_from_spec() {
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+
+    let gate = gate_from_spec("And", &[0, 1], &[out]).unwrap();
+    assert_eq!(gate.kind_name(), "And");
+
+    let mut simulation = compiler.compile(vec![&gate]).unwrap();
+    simulation.run(&[true, false]);
+    assert!(!simulation.registers[out]);
+    simulation.run(&[true, true]);
+    assert!(simulation.registers[out]);
+  }
+
+  #[test]
+  /// Test that a full adder built from a spec keeps the order of its ports
+  fn full_adder_from_spec() {
+    let gate = gate_from_spec("FullAdder", &[0, 1, 2], &[3, 4]).unwrap();
+
+    assert_eq!(gate.kind_name(), "FullAdder");
+    assert_eq!(gate.inputs(), vec![0, 1, 2]);
+    assert_eq!(gate.outputs(), vec![3, 4]);
+  }
+
+  #[test]
+  /// Test that every gate is rebuilt from its own name and registers
+  fn round_trip_names() {
+    for name in GATE_NAMES {
+      let width = match name {
+        "Encoder4to2" | "DFlipFlopSR" => 4,
+        "FourBitAdder" => 8,
+        "MagnitudeComparator" => 5,
+        _ => 3,
+      };
+      let inputs: Vec<usize> = (0..width).collect();
+      let mut gate = None;
+      for count in 1..=width {
+        let outputs: Vec<usize> = (width..width + count).collect();
+        for inputs in [&inputs[..0], &inputs[..1], &inputs[..2], &inputs[..]] {
+          if let Ok(built) = gate_from_spec(name, inputs, &outputs) {
+            gate = Some(built);
+          }
+        }
+      }
+
+      let gate = gate.unwrap_or_else(|| panic!("{} can't be built", name));
+      assert_eq!(gate.kind_name(), name);
+
+      let rebuilt =
+        gate_from_spec(name, &gate.inputs(), &gate.outputs()).unwrap();
+      assert_eq!(format!("{:?}", rebuilt), format!("{:?}", gate));
+    }
+  }
+
+  #[test]
+  /// Test that the catalog lists every gate once, with ports that build it
+  fn catalog_has_every_gate() {
+    let catalog = gate_catalog();
+    let mut names: Vec<&str> = catalog.iter().map(|info| info.name).collect();
+    names.sort();
+    let mut expected = GATE_NAMES.to_vec();
+    expected.sort();
+    assert_eq!(names, expected);
+
+    // Every kind of gate is matched, so a new gate doesn't compile here until
+    // it's named or left out of specs, and every named gate has to build
+    let spec_name = |gate: &Gate| match gate {
+      Gate::Nand(_) => Some("Nand"),
+      Gate::Not(_) => Some("Not"),
+      Gate::Buffer(_) => Some("Buffer"),
+      Gate::And(_) => Some("And"),
+      Gate::Or(_) => Some("Or"),
+      Gate::Nor(_) => Some("Nor"),
+      Gate::Xor(_) => Some("Xor"),
+      Gate::And3(_) => Some("And3"),
+      Gate::Or3(_) => Some("Or3"),
+      Gate::Constant(_) => Some("Constant"),
+      Gate::Encoder4to2(_) => Some("Encoder4to2"),
+      Gate::TriState(_) => Some("TriState"),
+      Gate::Mux2(_) => Some("Mux2"),
+      Gate::MuxWide(_) => Some("MuxWide"),
+      Gate::Rom(_) => Some("Rom"),
+      Gate::RSLatch(_) => Some("RSLatch"),
+      Gate::NandRSLatch(_) => Some("NandRSLatch"),
+      Gate::GatedSRLatch(_) => Some("GatedSRLatch"),
+      Gate::DLatch(_) => Some("DLatch"),
+      Gate::DFlipFlop(_) => Some("DFlipFlop"),
+      Gate::DFlipFlopSR(_) => Some("DFlipFlopSR"),
+      Gate::ShiftRegister(_) => Some("ShiftRegister"),
+      Gate::AsyncCounter(_) => Some("AsyncCounter"),
+      Gate::HalfAdder(_) => Some("HalfAdder"),
+      Gate::FullAdder(_) => Some("FullAdder"),
+      Gate::FourBitAdder(_) => Some("FourBitAdder"),
+      Gate::RippleCarryAdder(_) => Some("RippleCarryAdder"),
+      Gate::MagnitudeComparator(_) => Some("MagnitudeComparator"),
+      Gate::Multiplier(_) => Some("Multiplier"),
+      Gate::RSLatchTest(_) | Gate::UserGate(_) | Gate::Custom(_) => None,
+    };
+    for name in GATE_NAMES {
+      let gate = (0..=8)
+        .flat_map(|inputs| (1..=8).map(move |outputs| (inputs, outputs)))
+        .find_map(|(inputs, outputs)| {
+          let inputs: Vec<usize> = (0..inputs).collect();
+          let outputs: Vec<usize> = (8..8 + outputs).collect();
+          gate_from_spec(name, &inputs, &outputs).ok()
+        })
+        .unwrap_or_else(|| panic!("{} can't be built", name));
+      assert_eq!(spec_name(&gate), Some(name));
+    }
+
+    for info in catalog.iter() {
+      let fixed =
+        |ports: &[&str]| !ports.iter().any(|port| port.ends_with("[]"));
+      if fixed(info.inputs) && fixed(info.outputs) {
+        let inputs: Vec<usize> = (0..info.inputs.len()).collect();
+        let outputs: Vec<usize> =
+          (inputs.len()..inputs.len() + info.outputs.len()).collect();
+        assert!(
+          gate_from_spec(info.name, &inputs, &outputs).is_ok(),
+          "{} doesn't match its ports",
+          info.name
+        );
+      }
+    }
+
+    assert_eq!(gate_info("Xor").map(|info| info.category), Some("Basic"));
+    assert_eq!(gate_info("Mux"), None);
+  }
+
+  #[test]
+  /// Test that unknown names and the wrong number of ports are rejected
+  fn reject_invalid_specs() {
+    assert_eq!(
+      gate_from_spec("Mux", &[0, 1, 2], &[3]).unwrap_err(),
+      ParseError::UnknownName("Mux".to_string())
+    );
+    assert_eq!(
+      gate_from_spec("And", &[0, 1, 2], &[3]).unwrap_err(),
+      ParseError::Arity {
+        name: "And".to_string(),
+        inputs: 3,
+        outputs: 1,
+      }
+    );
+
+    // A word for each of 2^64 addresses doesn't fit in memory
+    let address: Vec<usize> = (0..64).collect();
+    assert_eq!(
+      gate_from_spec("Rom", &address, &[64]).unwrap_err(),
+      ParseError::AddressTooWide { bits: 64 }
+    );
+  }
+}
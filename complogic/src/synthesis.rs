@@ -0,0 +1,485 @@
+use std::{cmp::Reverse, fmt};
+
+use crate::{
+  from_bits, to_bits, And, Compiler, Constant, Gate, Not, Or, Simulation,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynthesisError {
+  /// The truth table doesn't have any inputs
+  NoInputs,
+
+  /// The row at the index doesn't have a value for each input
+  RowWidth {
+    row: usize,
+    expected: usize,
+    found: usize,
+  },
+}
+
+impl fmt::Display for SynthesisError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SynthesisError::NoInputs => {
+        write!(f, "a truth table needs at least one input")
+      }
+      SynthesisError::RowWidth {
+        row,
+        expected,
+        found,
+      } => write!(
+        f,
+        "row {} has {} values, expected one for each of the {} inputs",
+        row, found, expected
+      ),
+    }
+  }
+}
+
+impl std::error::Error for SynthesisError {}
+
+/// Synthesizes a sum of products circuit from a truth table
+///
+/// The inputs of the function are the first `inputs` registers (the
+/// immediates) and each row maps the values of those registers to an output.
+/// Rows with an output of `None` are don't-cares, which can be either value
+/// if that takes fewer gates. Rows that aren't listed are low. The last gate
+/// is always an Or and its output is the output of the function.
+pub fn synthesize(
+  inputs: usize,
+  rows: &[(Vec<bool>, Option<bool>)],
+  compiler: &mut Compiler,
+) -> Result<Vec<Gate>, SynthesisError> {
+  let inputs: Vec<usize> = (0..inputs).collect();
+  synthesize_from(&inputs, rows, compiler)
+}
+
+/// Synthesizes a sum of products circuit from a truth table over any
+/// registers
+///
+/// Works like [`synthesize`], except each row maps the values of the
+/// `inputs` registers to an output. Nothing is allocated if the table
+/// doesn't have any inputs or a row doesn't match them.
+pub fn synthesize_from(
+  inputs: &[usize],
+  rows: &[(Vec<bool>, Option<bool>)],
+  compiler: &mut Compiler,
+) -> Result<Vec<Gate>, SynthesisError> {
+  if inputs.is_empty() {
+    return Err(SynthesisError::NoInputs);
+  }
+  if let Some((row, (values, _))) = rows
+    .iter()
+    .enumerate()
+    .find(|(_, (values, _))| values.len() != inputs.len())
+  {
+    return Err(SynthesisError::RowWidth {
+      row,
+      expected: inputs.len(),
+      found: values.len(),
+    });
+  }
+
+  let mut gates: Vec<Gate> = vec![];
+
+  // Inverted copies of each input for the minterms to use
+  let inverted: Vec<usize> = inputs
+    .iter()
+    .map(|a| {
+      let not = Not {
+        a: *a,
+        out: compiler.alloc(),
+      };
+      gates.push(Gate::from(not));
+      not.out
+    })
+    .collect();
+
+  // And together the true or inverted inputs of each implicant. An
+  // implicant that covers every row is always high.
+  let mut terms: Vec<usize> = vec![];
+  for implicant in cover(rows) {
+    let mut literals = implicant.iter().enumerate().filter_map(|(i, bit)| {
+      bit.map(|bit| if bit { inputs[i] } else { inverted[i] })
+    });
+
+    let mut term = match literals.next() {
+      Some(literal) => literal,
+      None => {
+        let high = Constant {
+          value: true,
+          out: compiler.alloc(),
+        };
+        gates.push(Gate::from(high));
+        high.out
+      }
+    };
+    for literal in literals {
+      let and = And {
+        a: term,
+        b: literal,
+        out: compiler.alloc(),
+      };
+      gates.push(Gate::from(and));
+      term = and.out;
+    }
+
+    terms.push(term);
+  }
+
+  // Without any high rows, the function is always low
+  if terms.is_empty() {
+    let low = Constant {
+      value: false,
+      out: compiler.alloc(),
+    };
+    gates.push(Gate::from(low));
+    terms.push(low.out);
+  }
+
+  // Or together the minterms. A single minterm is Or'd with itself so the
+  // function always ends with an Or.
+  if terms.len() == 1 {
+    terms.push(terms[0]);
+  }
+
+  let mut out = terms[0];
+  for term in terms.into_iter().skip(1) {
+    let or = Or {
+      a: out,
+      b: term,
+      out: compiler.alloc(),
+    };
+    gates.push(Gate::from(or));
+    out = or.out;
+  }
+
+  Ok(gates)
+}
+
+/// A product of inputs, where `None` is an input that isn't part of it
+type Implicant = Vec<Option<bool>>;
+
+/// Finds a small set of implicants that covers every high row, using the
+/// don't-care rows to merge them
+///
+/// The prime implicants are found with Quine-McCluskey and then picked
+/// greedily, taking the one that covers the most uncovered high rows next.
+fn cover(rows: &[(Vec<bool>, Option<bool>)]) -> Vec<Implicant> {
+  let high: Vec<&Vec<bool>> = rows
+    .iter()
+    .filter(|(_, out)| *out == Some(true))
+    .map(|(row, _)| row)
+    .collect();
+
+  let mut implicants: Vec<Implicant> = rows
+    .iter()
+    .filter(|(_, out)| *out != Some(false))
+    .map(|(row, _)| row.iter().map(|bit| Some(*bit)).collect())
+    .collect();
+  implicants.sort();
+  implicants.dedup();
+
+  // Merge implicants that differ in a single input until none can be
+  // merged, keeping the ones that never merged as primes
+  let mut primes: Vec<Implicant> = vec![];
+  while !implicants.is_empty() {
+    let mut merged: Vec<Implicant> = vec![];
+    let mut used = vec![false; implicants.len()];
+    for i in 0..implicants.len() {
+      for j in i + 1..implicants.len() {
+        let differences: Vec<usize> = (0..implicants[i].len())
+          .filter(|k| implicants[i][*k] != implicants[j][*k])
+          .collect();
+        if let [k] = differences[..] {
+          if implicants[i][k].is_some() && implicants[j][k].is_some() {
+            let mut implicant = implicants[i].clone();
+            implicant[k] = None;
+            merged.push(implicant);
+            used[i] = true;
+            used[j] = true;
+          }
+        }
+      }
+    }
+
+    primes.extend(
+      implicants
+        .into_iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(implicant, _)| implicant),
+    );
+    merged.sort();
+    merged.dedup();
+    implicants = merged;
+  }
+
+  let covers = |implicant: &Implicant, row: &Vec<bool>| {
+    implicant
+      .iter()
+      .zip(row.iter())
+      .all(|(bit, value)| *bit != Some(!*value))
+  };
+
+  let mut uncovered = high;
+  let mut picked: Vec<Implicant> = vec![];
+  while !uncovered.is_empty() {
+    let best = primes
+      .iter()
+      .max_by_key(|prime| {
+        let count = uncovered.iter().filter(|row| covers(prime, row)).count();
+        let literals = prime.iter().filter(|bit| bit.is_some()).count();
+        (count, Reverse(literals))
+      })
+      .expect("every high row is covered by a prime")
+      .clone();
+
+    uncovered.retain(|row| !covers(&best, row));
+    picked.push(best);
+  }
+
+  picked
+}
+
+/// A Karnaugh map of an output, with the rows and columns in Gray code order
+/// so neighbouring cells differ in a single input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KMap {
+  /// The inputs that pick the row, the first being the most significant
+  pub row_inputs: Vec<usize>,
+
+  /// The inputs that pick the column, the first being the most significant
+  pub column_inputs: Vec<usize>,
+
+  /// The value of the output for each row, then each column
+  pub cells: Vec<Vec<bool>>,
+}
+
+/// Builds the Karnaugh map of `output` from the truth table of 2 to 4
+/// `inputs`
+///
+/// The first half of the inputs (rounded down) pick the row and the rest
+/// pick the column. The sweep is the same as [`Simulation::truth_table`], so
+/// the registers are left as they are.
+pub fn karnaugh_map(
+  sim: &mut Simulation,
+  inputs: &[usize],
+  output: usize,
+) -> KMap {
+  assert!(
+    (2..=4).contains(&inputs.len()),
+    "a Karnaugh map needs 2 to 4 inputs"
+  );
+
+  let table = sim.truth_table(inputs, &[output]);
+  let (row_inputs, column_inputs) = inputs.split_at(inputs.len() / 2);
+  let cells = gray_code(row_inputs.len())
+    .iter()
+    .map(|row| {
+      gray_code(column_inputs.len())
+        .iter()
+        .map(|column| {
+          let bits: Vec<bool> =
+            row.iter().chain(column.iter()).copied().collect();
+          table[from_bits(&bits)].1[0]
+        })
+        .collect()
+    })
+    .collect();
+
+  KMap {
+    row_inputs: row_inputs.to_vec(),
+    column_inputs: column_inputs.to_vec(),
+    cells,
+  }
+}
+
+/// Gets every value of `width` bits in Gray code order
+fn gray_code(width: usize) -> Vec<Vec<bool>> {
+  (0..1usize << width)
+    .map(|i| to_bits(i ^ (i >> 1), width))
+    .collect()
+}
+
+impl fmt::Display for KMap {
+  /// Renders the map as an ASCII grid, labelled with the registers of the
+  /// inputs and the value of each row and column
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let names = |inputs: &[usize]| -> String {
+      inputs.iter().map(|input| format!("r{}", input)).collect()
+    };
+    let bits = |bits: &[bool]| -> String {
+      bits
+        .iter()
+        .map(|bit| if *bit { '1' } else { '0' })
+        .collect()
+    };
+
+    let label = format!(
+      "{}\\{}",
+      names(&self.row_inputs),
+      names(&self.column_inputs)
+    );
+    let width = self.column_inputs.len();
+    let columns = gray_code(width);
+
+    write!(f, "{} |", label)?;
+    for column in columns.iter() {
+      write!(f, " {}", bits(column))?;
+    }
+    writeln!(f)?;
+    writeln!(
+      f,
+      "{}-+{}",
+      "-".repeat(label.len()),
+      "-".repeat((width + 1) * columns.len())
+    )?;
+
+    for (row, cells) in gray_code(self.row_inputs.len()).iter().zip(&self.cells)
+    {
+      write!(f, "{:>1$} |", bits(row), label.len())?;
+      for cell in cells {
+        write!(f, " {:>1$}", u8::from(*cell), width)?;
+      }
+      writeln!(f)?;
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::Xor;
+
+  #[test]
+  /// Test that synthesizing the Xor truth table simulates as a Xor
+  fn synthesize_xor() {
+    let mut compiler = Compiler::new(2);
+    let rows = [
+      (vec![false, false], Some(false)),
+      (vec![false, true], Some(true)),
+      (vec![true, false], Some(true)),
+      (vec![true, true], Some(false)),
+    ];
+
+    let gates = synthesize(2, &rows, &mut compiler).unwrap();
+    let out = match gates.last() {
+      Some(Gate::Or(or)) => or.out,
+      _ => panic!("The last gate should be an Or"),
+    };
+
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+    for (inputs, expected) in rows.iter() {
+      simulation.run(inputs);
+      assert_eq!(Some(simulation.registers[out]), *expected);
+    }
+  }
+
+  #[test]
+  /// Test that don't-cares are used to merge rows into fewer gates
+  fn synthesize_with_dont_cares() {
+    // High when the last input is high, except that it doesn't matter when
+    // every input is high
+    let rows: Vec<(Vec<bool>, Option<bool>)> = (0..8)
+      .map(|value| {
+        let inputs = to_bits(value, 3);
+        let out = match (inputs[0], inputs[1], inputs[2]) {
+          (true, true, true) => None,
+          (_, _, c) => Some(c),
+        };
+        (inputs, out)
+      })
+      .collect();
+
+    let mut compiler = Compiler::new(3);
+    let gates = synthesize(3, &rows, &mut compiler).unwrap();
+    let out = gates.last().unwrap().outputs()[0];
+
+    let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+    for (inputs, expected) in rows.iter() {
+      simulation.run(inputs);
+      if let Some(expected) = expected {
+        assert_eq!(simulation.registers[out], *expected, "{:?}", inputs);
+      }
+    }
+
+    // Treating the don't-care as low needs more gates
+    let low: Vec<(Vec<bool>, Option<bool>)> = rows
+      .iter()
+      .map(|(inputs, out)| (inputs.clone(), Some(out.unwrap_or(false))))
+      .collect();
+    let mut compiler = Compiler::new(3);
+    assert!(gates.len() < synthesize(3, &low, &mut compiler).unwrap().len());
+  }
+
+  #[test]
+  /// Test that functions that are always high or always low are built from
+  /// constants
+  fn synthesize_constants() {
+    for value in [false, true] {
+      let rows: Vec<(Vec<bool>, Option<bool>)> =
+        (0..4).map(|i| (to_bits(i, 2), Some(value))).collect();
+
+      let mut compiler = Compiler::new(2);
+      let gates = synthesize(2, &rows, &mut compiler).unwrap();
+      assert!(gates.iter().any(|gate| matches!(
+        gate,
+        Gate::Constant(constant) if constant.value == value
+      )));
+
+      let out = gates.last().unwrap().outputs()[0];
+      let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+      for (inputs, _) in rows.iter() {
+        simulation.run(inputs);
+        assert_eq!(simulation.registers[out], value);
+      }
+    }
+  }
+
+  #[test]
+  /// Test that tables without inputs or with a row of the wrong width are
+  /// rejected
+  fn synthesize_errors() {
+    let mut compiler = Compiler::new(2);
+    assert_eq!(
+      synthesize(0, &[], &mut compiler).unwrap_err(),
+      SynthesisError::NoInputs
+    );
+    assert_eq!(
+      synthesize(
+        2,
+        &[(vec![false, true], Some(true)), (vec![true], Some(false))],
+        &mut compiler
+      )
+      .unwrap_err(),
+      SynthesisError::RowWidth {
+        row: 1,
+        expected: 2,
+        found: 1,
+      }
+    );
+    assert_eq!(compiler.alloc(), 2);
+  }
+
+  #[test]
+  /// Test that the Karnaugh map of an Xor has ones on its diagonal
+  fn karnaugh_map_xor() {
+    let mut compiler = Compiler::new(2);
+    let out = compiler.alloc();
+    let mut simulation = compiler
+      .compile(vec![&Gate::from(Xor { a: 0, b: 1, out })])
+      .unwrap();
+
+    let map = karnaugh_map(&mut simulation, &[0, 1], out);
+    assert_eq!(map.row_inputs, vec![0]);
+    assert_eq!(map.column_inputs, vec![1]);
+    assert_eq!(map.cells, vec![vec![false, true], vec![true, false]]);
+    assert_eq!(
+      map.to_string(),
+      "r0\\r1 | 0 1\n------+----\n    0 | 0 1\n    1 | 1 0\n"
+    );
+  }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use crate::Simulation;
+
+/// The most runs a tick waits for the simulation to settle
+const MAX_RUNS: usize = 100;
+
+/// Two clocks that take turns being high, with both low in between so they
+/// never overlap
+///
+/// A cycle is four phases: phi1 high, both low, phi2 high, then both low.
+#[derive(Debug, Clone, Copy)]
+pub struct TwoPhaseClock {
+  /// The register of the first clock
+  pub phi1: usize,
+
+  /// The register of the second clock
+  pub phi2: usize,
+
+  /// The phase of the cycle the clocks are in
+  pub phase: usize,
+}
+
+impl TwoPhaseClock {
+  /// The number of phases in a cycle
+  pub const PHASES: usize = 4;
+
+  /// Creates a clock with both low at the end of a cycle, so the next tick
+  /// raises phi1
+  pub fn new(phi1: usize, phi2: usize) -> Self {
+    Self {
+      phi1,
+      phi2,
+      phase: Self::PHASES - 1,
+    }
+  }
+
+  /// Gets the values of phi1 and phi2 in the current phase
+  pub fn values(&self) -> (bool, bool) {
+    (self.phase == 0, self.phase == 2)
+  }
+
+  /// Advances to the next phase and gets the values of phi1 and phi2
+  pub fn tick(&mut self) -> (bool, bool) {
+    self.phase = (self.phase + 1) % Self::PHASES;
+    self.values()
+  }
+
+  /// Writes the values of phi1 and phi2 into the immediates
+  pub fn apply(&self, immediates: &mut [bool]) {
+    let (phi1, phi2) = self.values();
+    immediates[self.phi1] = phi1;
+    immediates[self.phi2] = phi2;
+  }
+}
+
+/// Drives a clocked simulation with named inputs, recording its named outputs
+/// after every tick
+#[derive(Debug, Clone)]
+pub struct Testbench {
+  pub simulation: Simulation,
+
+  /// The register of each input
+  pub inputs: HashMap<String, usize>,
+
+  /// The register of each output
+  pub outputs: HashMap<String, usize>,
+
+  /// The register of the clock
+  pub clock: usize,
+
+  /// The outputs after each tick
+  pub history: Vec<HashMap<String, bool>>,
+
+  /// Clocks driven through a full cycle on each tick instead of pulsing
+  /// `clock`
+  pub two_phase: Option<TwoPhaseClock>,
+}
+
+impl Testbench {
+  /// Creates a new testbench
+  pub fn new(
+    simulation: Simulation,
+    inputs: HashMap<String, usize>,
+    outputs: HashMap<String, usize>,
+    clock: usize,
+  ) -> Self {
+    Self {
+      simulation,
+      inputs,
+      outputs,
+      clock,
+      history: vec![],
+      two_phase: None,
+    }
+  }
+
+  /// Drives the simulation with a two-phase clock instead of `clock`
+  pub fn with_two_phase_clock(mut self, clock: TwoPhaseClock) -> Self {
+    self.two_phase = Some(clock);
+    self
+  }
+
+  /// Applies the inputs, pulses the clock low then high, and records the
+  /// outputs once the simulation settles
+  ///
+  /// With a two-phase clock, the simulation settles in each phase of a full
+  /// cycle instead, starting from the phase after the last tick.
+  ///
+  /// Inputs that aren't given are low. Panics if an input isn't known or the
+  /// simulation doesn't settle.
+  pub fn tick(
+    &mut self,
+    inputs: &HashMap<String, bool>,
+  ) -> &HashMap<String, bool> {
+    let mut immediates = vec![false; self.simulation.immediate_count];
+    for (name, value) in inputs.iter() {
+      let reg = match self.inputs.get(name) {
+        Some(reg) => *reg,
+        None => panic!("unknown input {}", name),
+      };
+      immediates[reg] = *value;
+    }
+
+    match self.two_phase.as_mut() {
+      Some(clock) => {
+        for _ in 0..TwoPhaseClock::PHASES {
+          clock.tick();
+          clock.apply(&mut immediates);
+          assert!(
+            self.simulation.run_until_stable(&immediates, MAX_RUNS),
+            "the simulation didn't settle"
+          );
+        }
+      }
+      None => {
+        for clock in [false, true] {
+          immediates[self.clock] = clock;
+          assert!(
+            self.simulation.run_until_stable(&immediates, MAX_RUNS),
+            "the simulation didn't settle"
+          );
+        }
+      }
+    }
+
+    let outputs = self
+      .outputs
+      .iter()
+      .map(|(name, reg)| (name.clone(), self.simulation.registers[*reg]))
+      .collect();
+    self.history.push(outputs);
+
+    &self.history[self.history.len() - 1]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Compiler, DFlipFlop, DLatch, Gate, Polarity};
+
+  #[test]
+  /// Test that a D flip-flop outputs the input of each tick
+  fn d_flip_flop() {
+    let mut compiler = Compiler::new(2);
+    let [d, clk] = [0, 1];
+
+    let d_flip_flop = DFlipFlop {
+      d,
+      clk,
+      q: compiler.alloc(),
+    };
+    let simulation = compiler.compile(vec![&Gate::from(d_flip_flop)]).unwrap();
+
+    let mut testbench = Testbench::new(
+      simulation,
+      HashMap::from([("d".into(), d)]),
+      HashMap::from([("q".into(), d_flip_flop.q)]),
+      clk,
+    );
+
+    let bits = [true, false, false, true, true, false];
+    for bit in bits {
+      testbench.tick(&HashMap::from([("d".into(), bit)]));
+    }
+
+    let q: Vec<bool> = testbench
+      .history
+      .iter()
+      .map(|outputs| outputs["q"])
+      .collect();
+    assert_eq!(q, bits);
+  }
+
+  #[test]
+  /// Test that the phases of a two-phase clock never overlap
+  fn two_phase_clock() {
+    let mut clock = TwoPhaseClock::new(0, 1);
+    let mut phases = vec![clock.values()];
+    for _ in 0..TwoPhaseClock::PHASES {
+      phases.push(clock.tick());
+    }
+
+    assert!(phases.iter().all(|(phi1, phi2)| !(*phi1 && *phi2)));
+    assert_eq!(
+      phases,
+      [
+        (false, false),
+        (true, false),
+        (false, false),
+        (false, true),
+        (false, false),
+      ]
+    );
+  }
+
+  #[test]
+  /// Test that a latch pair clocked by two phases outputs the input of each
+  /// tick
+  fn two_phase_latches() {
+    let mut compiler = Compiler::new(3);
+    let [d, phi1, phi2] = [0, 1, 2];
+
+    let master = DLatch {
+      d,
+      e: phi1,
+      q: compiler.alloc(),
+      polarity: Polarity::High,
+    };
+    let slave = DLatch {
+      d: master.q,
+      e: phi2,
+      q: compiler.alloc(),
+      polarity: Polarity::High,
+    };
+    let simulation = compiler
+      .compile(vec![&Gate::from(master), &Gate::from(slave)])
+      .unwrap();
+
+    let mut testbench = Testbench::new(
+      simulation,
+      HashMap::from([("d".into(), d)]),
+      HashMap::from([("q".into(), slave.q)]),
+      phi1,
+    )
+    .with_two_phase_clock(TwoPhaseClock::new(phi1, phi2));
+
+    let bits = [true, false, false, true, true, false];
+    for bit in bits {
+      testbench.tick(&HashMap::from([("d".into(), bit)]));
+      let registers = &testbench.simulation.registers;
+      assert!(!(registers[phi1] && registers[phi2]));
+    }
+
+    let q: Vec<bool> = testbench
+      .history
+      .iter()
+      .map(|outputs| outputs["q"])
+      .collect();
+    assert_eq!(q, bits);
+  }
+}
@@ -0,0 +1,96 @@
+/// Converts a number to `width` bits, most significant bit first
+///
+/// Bits above `width` are truncated, so only the low `width` bits of `value`
+/// are kept.
+pub fn to_bits(value: usize, width: usize) -> Vec<bool> {
+  (0..width)
+    .rev()
+    .map(|i| i < usize::BITS as usize && (value >> i) & 1 == 1)
+    .collect()
+}
+
+/// Converts bits, most significant bit first, to a number
+pub fn from_bits(bits: &[bool]) -> usize {
+  bits
+    .iter()
+    .fold(0, |value, bit| (value << 1) | usize::from(*bit))
+}
+
+/// Gets every input one bit flip away from `base`, flipping each bit in turn
+pub fn adjacent_vectors(base: &[bool]) -> Vec<Vec<bool>> {
+  (0..base.len())
+    .map(|i| {
+      let mut vector = base.to_vec();
+      vector[i] = !vector[i];
+      vector
+    })
+    .collect()
+}
+
+/// Gets `width` inputs with a single high bit, walking it from the first bit
+/// to the last
+pub fn walking_ones(width: usize) -> Vec<Vec<bool>> {
+  adjacent_vectors(&vec![false; width])
+}
+
+/// Gets `width` inputs with a single low bit, walking it from the first bit
+/// to the last
+pub fn walking_zeros(width: usize) -> Vec<Vec<bool>> {
+  adjacent_vectors(&vec![true; width])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  /// Test that values convert to bits and back
+  fn round_trip() {
+    assert_eq!(to_bits(0b1011, 4), vec![true, false, true, true]);
+    assert_eq!(to_bits(1, 3), vec![false, false, true]);
+    assert_eq!(to_bits(0, 0), Vec::<bool>::new());
+
+    for value in 0..32 {
+      assert_eq!(from_bits(&to_bits(value, 5)), value);
+    }
+  }
+
+  #[test]
+  /// Test that bits above the width are dropped
+  fn truncation() {
+    assert_eq!(to_bits(0b10110, 3), vec![true, true, false]);
+    assert_eq!(from_bits(&to_bits(0b10110, 3)), 0b110);
+    assert_eq!(to_bits(usize::MAX, 70).iter().filter(|b| **b).count(), 64);
+  }
+
+  #[test]
+  /// Test that each generated vector is one bit flip from its base
+  fn test_vectors() {
+    let base = [true, false, true];
+    let adjacent = adjacent_vectors(&base);
+    assert_eq!(
+      adjacent,
+      [
+        [false, false, true],
+        [true, true, true],
+        [true, false, false],
+      ]
+    );
+    assert!(adjacent_vectors(&[]).is_empty());
+
+    let ones = walking_ones(4);
+    assert_eq!(ones.len(), 4);
+    for (i, vector) in ones.iter().enumerate() {
+      assert_eq!(from_bits(vector), 1 << (3 - i));
+    }
+
+    let zeros = walking_zeros(4);
+    assert_eq!(zeros.len(), 4);
+    for (vector, ones) in zeros.iter().zip(ones.iter()) {
+      assert!(vector
+        .iter()
+        .zip(ones.iter())
+        .all(|(zero, one)| zero != one));
+    }
+  }
+}
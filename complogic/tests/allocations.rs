@@ -0,0 +1,56 @@
+//! Tests that count allocations, which are kept in their own test binary so
+//! the counting allocator doesn't apply to any other tests
+
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  cell::Cell,
+};
+
+use complogic::{Compiler, Gate, Not};
+
+/// Counts the allocations made by each thread, so the test harness doesn't
+/// count toward the test
+struct CountingAlloc;
+
+thread_local! {
+  static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+/// Test that checking for stability doesn't allocate once the buffer of
+/// previous registers exists
+fn run_until_stable_doesnt_allocate() {
+  let mut compiler = Compiler::new(1);
+  let mut gates = vec![];
+  let mut a = 0;
+  for _ in 0..10_000 {
+    let out = compiler.alloc();
+    gates.push(Gate::from(Not { a, out }));
+    a = out;
+  }
+  let mut simulation = compiler.compile(gates.iter().collect()).unwrap();
+  assert!(simulation.run_until_stable(&[true], 10));
+
+  let before = ALLOCATIONS.with(|count| count.get());
+  for i in 0..10 {
+    assert!(simulation.run_until_stable(&[i % 2 == 0], 10));
+  }
+  assert_eq!(ALLOCATIONS.with(|count| count.get()), before);
+
+  // An even number of Nots passes the last immediate through
+  assert!(!simulation.registers[a]);
+}
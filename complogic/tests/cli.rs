@@ -0,0 +1,40 @@
+use std::process::Command;
+
+/// Runs the CLI on the bundled full adder with the given inputs
+fn run_full_adder(inputs: &[&str]) -> std::process::Output {
+  Command::new(env!("CARGO_BIN_EXE_complogic-sim"))
+    .arg(concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/tests/full_adder.json"
+    ))
+    .args(inputs)
+    .output()
+    .unwrap()
+}
+
+#[test]
+/// Test that the CLI prints the named outputs of the full adder
+fn full_adder() {
+  let cases = [
+    (vec![], "s=0\ncout=0\n"),
+    (vec!["a=1"], "s=1\ncout=0\n"),
+    (vec!["a=1", "b=true"], "s=0\ncout=1\n"),
+    (vec!["a=1", "b=1", "cin=1"], "s=1\ncout=1\n"),
+  ];
+
+  for (inputs, expected) in cases {
+    let output = run_full_adder(&inputs);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+  }
+}
+
+#[test]
+/// Test that the CLI fails on an input the circuit doesn't have
+fn unknown_input() {
+  let output = run_full_adder(&["d=1"]);
+  assert!(!output.status.success());
+  assert!(String::from_utf8(output.stderr)
+    .unwrap()
+    .contains("unknown input d"));
+}